@@ -0,0 +1,19 @@
+//! Records the target triple and the rustc version ketch is built with, for
+//! `ketch --version --format json`.
+
+use std::{env, process::Command};
+
+fn main() {
+    let target = env::var("TARGET").unwrap_or_default();
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let version = Command::new(&rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=KETCH_TARGET={}", target);
+    println!("cargo:rustc-env=KETCH_RUSTC_VERSION={}", version);
+    println!("cargo:rerun-if-changed=build.rs");
+}
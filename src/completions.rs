@@ -0,0 +1,235 @@
+use ketch::{error, errors::Result};
+
+const BASH: &str = r#"_ketch() {
+    local cur prev words cword
+    _init_completion || return
+    local commands="new init build check info doctor add remove graph completions"
+    local i command
+    for ((i = 1; i < cword; i++)); do
+        case "${words[i]}" in
+            -C|--directory|--timeout) ((i++)) ;;
+            -*) ;;
+            *) command="${words[i]}"; break ;;
+        esac
+    done
+    case "$prev" in
+        -C|--directory|--output-dir) _filedir -d; return ;;
+        --log|--manifest) _filedir; return ;;
+        --format) [[ $command == graph ]] && COMPREPLY=($(compgen -W "dot json" -- "$cur")) || COMPREPLY=($(compgen -W "text json" -- "$cur")); return ;;
+        --emit) COMPREPLY=($(compgen -W "bin obj" -- "$cur")); return ;;
+        --opt-level) COMPREPLY=($(compgen -W "0 1 2 3 s z g" -- "$cur")); return ;;
+        --linker) COMPREPLY=($(compgen -W "bfd gold lld mold" -- "$cur")); return ;;
+        --std) COMPREPLY=($(compgen -W "ansi c89 c99 c11 c17 c23 c2x gnu89 gnu99 gnu11 gnu17 gnu23 gnu2x" -- "$cur")); return ;;
+    esac
+    case "$command" in
+        "") COMPREPLY=($(compgen -W "$commands -C --directory --timeout --help --version --format" -- "$cur")) ;;
+        new) COMPREPLY=($(compgen -W "-s --static -S --shared --git --help" -- "$cur")) ;;
+        init) COMPREPLY=($(compgen -W "--force --help" -- "$cur")) ;;
+        build) COMPREPLY=($(compgen -W "-k --keep-going --release --log --werror --no-werror --timings --explain --format --strict --both --compiler --std --print-flags --manifest --no-default-flags --emit --fail-verbose --check-flags --opt-level --no-debug --explain-config --output-dir --linker --unity --max-errors --help" -- "$cur")) ;;
+        check) COMPREPLY=($(compgen -W "-k --keep-going --manifest --fail-verbose --help" -- "$cur")) ;;
+        info|doctor|add|remove) COMPREPLY=($(compgen -W "--manifest --help" -- "$cur")) ;;
+        graph) COMPREPLY=($(compgen -W "--format --manifest --help" -- "$cur")) ;;
+        completions) COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur")) ;;
+    esac
+}
+complete -F _ketch ketch
+"#;
+
+const ZSH: &str = r#"#compdef ketch
+
+_ketch() {
+    local -a commands
+    commands=(
+        'new:Create a new ketch project'
+        'init:Create a ketchfile for the sources in the current directory'
+        'build:Build the project according to the ketchfile'
+        'check:Check that the project compiles, without building it'
+        'info:Display the project configuration and metadata'
+        'doctor:Check that the tools a build needs are installed'
+        'add:Declare a dependency in the ketchfile'
+        'remove:Remove a dependency and its installation'
+        'graph:Print the dependency graph in Graphviz DOT'
+        'completions:Print a shell completion script'
+    )
+    _arguments -C \
+        '(-C --directory)'{-C,--directory}'[Run in another directory]:directory:_directories' \
+        '--timeout[Kill commands after a number of seconds]:seconds:' \
+        '--help[Display help]' \
+        '--version[Display version information]' \
+        '--format[Version format]:format:(text json)' \
+        '1: :->command' \
+        '*:: :->args'
+    case $state in
+        command) _describe 'command' commands ;;
+        args)
+            case $words[1] in
+                new) _arguments \
+                    '(-s --static)'{-s,--static}'[Create a static library project]' \
+                    '(-S --shared)'{-S,--shared}'[Create a shared library project]' \
+                    '--git[Initialise a git repository]' \
+                    '--help[Display help]' \
+                    ':name:' ;;
+                init) _arguments \
+                    '--force[Overwrite an existing ketchfile]' \
+                    '--help[Display help]' ;;
+                build) _arguments \
+                    '(-k --keep-going)'{-k,--keep-going}'[Keep compiling after a failure]' \
+                    '--release[Build with optimisation flags]' \
+                    '--log[Also write the build transcript to a file]:file:_files' \
+                    '--werror[Treat warnings as errors]' \
+                    '--no-werror[Do not treat warnings as errors]' \
+                    '--timings[Report how long each file took to compile]' \
+                    '--explain[Explain why each file is compiled or skipped]' \
+                    '--format[Progress format]:format:(text json)' \
+                    '--strict[Turn configuration warnings into errors]' \
+                    '--both[Build both a static and a shared library]' \
+                    '--compiler[Compiler to use]:compiler:_command_names' \
+                    '--std[Standard to use]:standard:(ansi c89 c99 c11 c17 c23 c2x gnu89 gnu99 gnu11 gnu17 gnu23 gnu2x)' \
+                    '--print-flags[Print the compiler invocation]' \
+                    '--manifest[Configuration file]:file:_files' \
+                    '--no-default-flags[Compile without the configured flags]' \
+                    '--emit[What to produce]:kind:(bin obj)' \
+                    '--check-flags[Warn about flags missing their dash]' \
+                    '--fail-verbose[Only show failed commands]' \
+                    '--opt-level[Optimisation level]:level:(0 1 2 3 s z g)' \
+                    '--no-debug[Build without the debug flags]' \
+                    '--explain-config[Print where each setting comes from]' \
+                    '--output-dir[Build directory]:directory:_directories' \
+                    '--linker[Linker to use]:linker:(bfd gold lld mold)' \
+                    '--unity[Compile the sources as one translation unit]' \
+                    '--max-errors[Stop after a number of errors]:count:' \
+                    '--help[Display help]' ;;
+                check) _arguments \
+                    '(-k --keep-going)'{-k,--keep-going}'[Keep checking after a failure]' \
+                    '--fail-verbose[Only show failed commands]' \
+                    '--manifest[Configuration file]:file:_files' \
+                    '--help[Display help]' ;;
+                add|remove) _arguments \
+                    '--manifest[Configuration file]:file:_files' \
+                    '--help[Display help]' \
+                    ':dependency:' ;;
+                info|doctor) _arguments \
+                    '--manifest[Configuration file]:file:_files' \
+                    '--help[Display help]' ;;
+                graph) _arguments \
+                    '--format[Graph format]:format:(dot json)' \
+                    '--manifest[Configuration file]:file:_files' \
+                    '--help[Display help]' ;;
+                completions) _arguments ':shell:(bash zsh fish)' ;;
+            esac ;;
+    esac
+}
+
+_ketch "$@"
+"#;
+
+const FISH: &str = r#"set -l commands new init build check info doctor add remove graph completions
+complete -c ketch -f
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -s C -l directory -r -a '(__fish_complete_directories)' -d 'Run in another directory'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -l timeout -x -d 'Kill commands after a number of seconds'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -l help -d 'Display help'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -l version -d 'Display version information'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -l format -x -a 'text json' -d 'Version format'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -a new -d 'Create a new ketch project'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -a init -d 'Create a ketchfile for the sources in the current directory'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -a build -d 'Build the project according to the ketchfile'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -a check -d 'Check that the project compiles, without building it'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -a info -d 'Display the project configuration and metadata'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -a doctor -d 'Check that the tools a build needs are installed'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -a add -d 'Declare a dependency in the ketchfile'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -a remove -d 'Remove a dependency and its installation'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -a graph -d 'Print the dependency graph in Graphviz DOT'
+complete -c ketch -n "not __fish_seen_subcommand_from $commands" -a completions -d 'Print a shell completion script'
+complete -c ketch -n "__fish_seen_subcommand_from new" -s s -l static -d 'Create a static library project'
+complete -c ketch -n "__fish_seen_subcommand_from new" -s S -l shared -d 'Create a shared library project'
+complete -c ketch -n "__fish_seen_subcommand_from new" -l git -d 'Initialise a git repository'
+complete -c ketch -n "__fish_seen_subcommand_from build check" -s k -l keep-going -d 'Keep going after a failure'
+complete -c ketch -n "__fish_seen_subcommand_from build check" -l fail-verbose -d 'Only show failed commands'
+complete -c ketch -n "__fish_seen_subcommand_from build check info doctor add remove graph" -l manifest -r -F -d 'Configuration file'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l release -d 'Build with optimisation flags'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l log -r -F -d 'Also write the build transcript to a file'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l werror -d 'Treat warnings as errors'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l no-werror -d 'Do not treat warnings as errors'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l timings -d 'Report how long each file took to compile'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l explain -d 'Explain why each file is compiled or skipped'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l format -x -a 'text json' -d 'Progress format'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l strict -d 'Turn configuration warnings into errors'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l both -d 'Build both a static and a shared library'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l compiler -x -a '(__fish_complete_command)' -d 'Compiler to use'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l std -x -a 'ansi c89 c99 c11 c17 c23 c2x gnu89 gnu99 gnu11 gnu17 gnu23 gnu2x' -d 'Standard to use'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l print-flags -d 'Print the compiler invocation'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l emit -x -a 'bin obj' -d 'What to produce'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l check-flags -d 'Warn about flags missing their dash'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l opt-level -x -a '0 1 2 3 s z g' -d 'Optimisation level'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l linker -x -a 'bfd gold lld mold' -d 'Linker to use'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l unity -d 'Compile the sources as one translation unit'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l max-errors -x -d 'Stop after a number of errors'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l output-dir -r -a '(__fish_complete_directories)' -d 'Build directory'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l explain-config -d 'Print where each setting comes from'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l no-debug -d 'Build without the debug flags'
+complete -c ketch -n "__fish_seen_subcommand_from build" -l no-default-flags -d 'Compile without the configured flags'
+complete -c ketch -n "__fish_seen_subcommand_from init" -l force -d 'Overwrite an existing ketchfile'
+complete -c ketch -n "__fish_seen_subcommand_from new init build check info doctor add remove graph" -l help -d 'Display help'
+complete -c ketch -n "__fish_seen_subcommand_from graph" -l format -x -a 'dot json' -d 'Graph format'
+complete -c ketch -n "__fish_seen_subcommand_from completions" -x -a 'bash zsh fish'
+"#;
+
+/// The completion script for `shell`, written by hand since the command line
+/// is parsed with getopt.
+pub fn script(shell: &str) -> Result<&'static str> {
+    match shell {
+        "bash" => Ok(BASH),
+        "zsh" => Ok(ZSH),
+        "fish" => Ok(FISH),
+        x => error!(
+            "`{}` is not a supported shell. Available shells: bash, zsh, fish.",
+            x
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_command() -> Result<()> {
+        for shell in ["bash", "zsh", "fish"] {
+            let script = script(shell)?;
+            for word in [
+                "new",
+                "init",
+                "build",
+                "check",
+                "doctor",
+                "add",
+                "remove",
+                "graph",
+                "completions",
+                "manifest",
+                "keep-going",
+            ] {
+                assert!(
+                    script.contains(word),
+                    "{} completions lack `{}`",
+                    shell,
+                    word
+                );
+            }
+        }
+        assert!(script("powershell").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn standards() -> Result<()> {
+        let standards = "ansi c89 c99 c11 c17 c23 c2x gnu89 gnu99 gnu11 gnu17 gnu23 gnu2x";
+        for shell in ["bash", "zsh", "fish"] {
+            assert!(script(shell)?.contains(standards), "{}", shell);
+        }
+        for standard in standards.split(' ') {
+            ketch::parse_standard(standard)?;
+        }
+        Ok(())
+    }
+}
@@ -2,7 +2,10 @@ use crate::{
     error,
     errors::{Error, Result},
 };
-use std::fs;
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ConfigValue {
@@ -11,11 +14,35 @@ pub enum ConfigValue {
     Pair(String, Box<ConfigValue>),
     None,
 }
+impl Display for ConfigValue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Ident(s) if s.is_empty() || s.contains(|c: char| "\"\\() \t\r\n".contains(c)) => {
+                write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            Self::Ident(s) => write!(f, "{}", s),
+            Self::Array(values) => write!(f, "({})", join(values)),
+            Self::Pair(k, v) => match &**v {
+                Self::Array(values) if values.is_empty() => write!(f, "({})", k),
+                Self::Array(values) => write!(f, "({} {})", k, join(values)),
+                v => write!(f, "({} {})", k, v),
+            },
+            Self::None => Ok(()),
+        }
+    }
+}
+fn join(values: &[ConfigValue]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
 
 struct ConfigParser {
     current: usize,
     line: usize,
-    input: String,
+    input: Vec<char>,
     output: Vec<ConfigValue>,
 }
 impl ConfigParser {
@@ -23,7 +50,7 @@ impl ConfigParser {
         Self {
             current: 0,
             line: 1,
-            input: input.to_string(),
+            input: input.to_string().chars().collect(),
             output: vec![],
         }
     }
@@ -33,7 +60,7 @@ impl ConfigParser {
         c
     }
     fn peek(&self) -> Option<char> {
-        self.input.chars().nth(self.current)
+        self.input.get(self.current).copied()
     }
     fn is_at_end(&self) -> bool {
         self.current >= self.input.len()
@@ -46,26 +73,78 @@ impl ConfigParser {
         }
         Ok(out)
     }
-    fn parse_one(&mut self) -> Result<ConfigValue> {
+    fn parse_body(&mut self) -> Result<Vec<ConfigValue>> {
+        let mut body = vec![];
+        while !self.is_at_end() && self.peek() != Some(')') {
+            let val = self.parse_one(true)?;
+            if val != ConfigValue::None {
+                body.push(val)
+            }
+        }
+        if self.peek() != Some(')') {
+            error!("line {}: Expected `)`, found EOF.", self.line)
+        } else {
+            self.advance();
+            Ok(body)
+        }
+    }
+    fn parse_quoted(&mut self) -> Result<String> {
+        let line = self.line;
+        let mut out = String::new();
+        while let Some(c) = self.peek() {
+            self.advance();
+            match c {
+                '"' => return Ok(out),
+                '\\' if !self.is_at_end() => out.push(self.advance()),
+                '\n' => {
+                    self.line += 1;
+                    out.push(c)
+                }
+                c => out.push(c),
+            }
+        }
+        error!("line {}: Unterminated string.", line)
+    }
+    /// Parses the rest of a `$(command)` or `` `command` `` word, kept whole
+    /// so that the command can be run when the project is loaded.
+    fn parse_command(&mut self, first: char) -> Result<String> {
+        let line = self.line;
+        let mut out = first.to_string();
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            out.push(self.advance());
+            match c {
+                '\n' => self.line += 1,
+                '(' if first == '$' => depth += 1,
+                ')' if first == '$' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(out);
+                    }
+                }
+                '`' if first == '`' => return Ok(out),
+                _ => {}
+            }
+        }
+        error!("line {}: Unterminated command substitution.", line)
+    }
+    /// Parses a single value. A `(` opens a keyed pair at the top level and a
+    /// bare nested array inside a pair body.
+    fn parse_one(&mut self, nested: bool) -> Result<ConfigValue> {
         let current = self.advance();
         match current {
             ' ' | '\t' | '\r' => {}
             '\n' => self.line += 1,
+            '"' => return Ok(ConfigValue::Ident(self.parse_quoted()?)),
+            '$' if self.peek() == Some('(') => {
+                return Ok(ConfigValue::Ident(self.parse_command(current)?))
+            }
+            '`' => return Ok(ConfigValue::Ident(self.parse_command(current)?)),
+            '(' if nested => return Ok(ConfigValue::Array(self.parse_body()?)),
             '(' => {
                 let key = self.parse_ident()?;
-                let mut body = vec![];
-                while !self.is_at_end() && self.peek() != Some(')') {
-                    let val = self.parse_one()?;
-                    if val != ConfigValue::None {
-                        body.push(val)
-                    }
-                }
-                return if self.peek() != Some(')') {
-                    error!("line {}: Expected `)`, found EOF.", self.line)
-                } else {
-                    self.advance();
-                    Ok(ConfigValue::Pair(key, Box::new(ConfigValue::Array(body))))
-                };
+                let body = self.parse_body()?;
+                return Ok(ConfigValue::Pair(key, Box::new(ConfigValue::Array(body))));
             }
             x => {
                 let mut s = x.to_string();
@@ -77,7 +156,7 @@ impl ConfigParser {
     }
     pub fn parse(&mut self) -> Result<Vec<ConfigValue>> {
         while !self.is_at_end() {
-            let val = self.parse_one()?;
+            let val = self.parse_one(false)?;
             if val != ConfigValue::None {
                 self.output.push(val);
             }
@@ -86,12 +165,14 @@ impl ConfigParser {
     }
 }
 
+pub fn parse_string(input: impl ToString) -> Result<Vec<ConfigValue>> {
+    ConfigParser::new(input).parse()
+}
 pub fn parse_file(name: impl ToString) -> Result<Vec<ConfigValue>> {
-    ConfigParser::new(
-        fs::read_to_string(&name.to_string())
+    parse_string(
+        fs::read_to_string(name.to_string())
             .map_err(|e| Error(format!("Failed to read file: {}: {}.", name.to_string(), e)))?,
     )
-    .parse()
 }
 pub fn find_val(values: &[ConfigValue], key: impl ToString) -> Option<ConfigValue> {
     let key = key.to_string();
@@ -104,6 +185,153 @@ pub fn find_val(values: &[ConfigValue], key: impl ToString) -> Option<ConfigValu
     }
     None
 }
+/// Reads `key` as a single string, if present.
+pub fn get_string(values: &[ConfigValue], key: impl ToString) -> Result<Option<String>> {
+    let key = key.to_string();
+    match find_val(values, &key) {
+        None => Ok(None),
+        Some(ConfigValue::Array(av)) => get_first(&av, &key).map(Some),
+        _ => error!("Key `{}` must be a single string.", key),
+    }
+}
+/// Reads `key` as a list of strings, if present.
+pub fn get_list(values: &[ConfigValue], key: impl ToString) -> Result<Option<Vec<String>>> {
+    let key = key.to_string();
+    match find_val(values, &key) {
+        None => Ok(None),
+        Some(value) => as_list(value, &key).map(Some),
+    }
+}
+/// Reads every occurrence of `key` as a list of strings and concatenates them.
+pub fn get_all(values: &[ConfigValue], key: impl ToString) -> Result<Vec<String>> {
+    Ok(get_each(values, key)?.concat())
+}
+/// Reads every occurrence of `key` as a list of strings, one per occurrence.
+pub fn get_each(values: &[ConfigValue], key: impl ToString) -> Result<Vec<Vec<String>>> {
+    let key = key.to_string();
+    let mut each = vec![];
+    for val in values {
+        if let ConfigValue::Pair(k, v) = val {
+            if k.as_str() == key.as_str() {
+                each.push(as_list(*v.clone(), &key)?);
+            }
+        }
+    }
+    Ok(each)
+}
+fn as_list(value: ConfigValue, key: &str) -> Result<Vec<String>> {
+    if let ConfigValue::Array(av) = value {
+        let mut list = vec![];
+        for value in av {
+            if let ConfigValue::Ident(s) = value {
+                list.push(s);
+            } else {
+                return error!("Each element of key `{}` must be an identifier.", key);
+            }
+        }
+        Ok(list)
+    } else {
+        error!("Key `{}` must be an array.", key)
+    }
+}
+/// Reads `key` as a boolean, spelled as accepted by `as_bool`, if present.
+pub fn get_bool(values: &[ConfigValue], key: impl ToString) -> Result<Option<bool>> {
+    let key = key.to_string();
+    match find_val(values, &key) {
+        None => Ok(None),
+        Some(value) => as_bool(&value)
+            .map(Some)
+            .map_err(|e| Error(format!("Invalid value for key `{}`: {}", key, e.0))),
+    }
+}
+/// Reads a boolean spelled `true`/`false`, `yes`/`no`, `on`/`off` or `1`/`0`.
+/// A single element array, as in `(key yes)`, is read as its element.
+pub fn as_bool(value: &ConfigValue) -> Result<bool> {
+    match value {
+        ConfigValue::Array(av) if av.len() == 1 => as_bool(&av[0]),
+        ConfigValue::Ident(s) => match s.as_str() {
+            "true" | "yes" | "on" | "1" => Ok(true),
+            "false" | "no" | "off" | "0" => Ok(false),
+            x => error!(
+                "`{}` is not a boolean. Expected true, false, yes, no, on, off, 1 or 0.",
+                x
+            ),
+        },
+        _ => error!("Expected a single boolean (true, false, yes, no, on, off, 1 or 0)."),
+    }
+}
+/// The operating systems accepted by `platform` blocks, named as in
+/// `std::env::consts::OS`.
+const PLATFORMS: [&str; 8] = [
+    "linux",
+    "macos",
+    "windows",
+    "freebsd",
+    "openbsd",
+    "netbsd",
+    "dragonfly",
+    "android",
+];
+/// Replaces each `(platform OS (key value...)...)` block by its pairs when `os`
+/// is OS, and drops it otherwise. The pairs of matching blocks come first, so
+/// that they take precedence over the top-level ones.
+pub fn resolve_platforms(values: Vec<ConfigValue>, os: &str) -> Result<Vec<ConfigValue>> {
+    let mut matched = vec![];
+    let mut rest = vec![];
+    for value in values {
+        let body = match value {
+            ConfigValue::Pair(k, v) if k == "platform" => match *v {
+                ConfigValue::Array(body) => body,
+                _ => return error!("Key `platform` must be an array."),
+            },
+            value => {
+                rest.push(value);
+                continue;
+            }
+        };
+        let mut body = body.into_iter();
+        let platform = match body.next() {
+            Some(ConfigValue::Ident(platform)) => platform,
+            _ => return error!("Key `platform` must start with a platform name."),
+        };
+        if !PLATFORMS.contains(&platform.as_str()) {
+            return error!(
+                "`{}` is not a valid platform. Available platforms: {}.",
+                platform,
+                PLATFORMS.join(", ")
+            );
+        }
+        for pair in body {
+            let pair = match pair {
+                ConfigValue::Array(pair) => pair,
+                _ => return error!("Each element of platform `{}` must be a pair.", platform),
+            };
+            let mut pair = pair.into_iter();
+            match pair.next() {
+                Some(ConfigValue::Ident(key)) if platform == os => matched.push(ConfigValue::Pair(
+                    key,
+                    Box::new(ConfigValue::Array(pair.collect())),
+                )),
+                Some(ConfigValue::Ident(_)) => {}
+                _ => return error!("Each element of platform `{}` must be a pair.", platform),
+            }
+        }
+    }
+    matched.extend(rest);
+    Ok(matched)
+}
+fn get_first(av: &[ConfigValue], k: impl ToString) -> Result<String> {
+    let k = k.to_string();
+    if av.len() == 1 {
+        if let ConfigValue::Ident(name) = &av[0] {
+            Ok(name.to_string())
+        } else {
+            error!("Key `{}` must be a single string.", k)
+        }
+    } else {
+        error!("Key `{}` must be a single string.", k)
+    }
+}
 #[cfg(test)]
 mod test {
     use super::*;
@@ -137,4 +365,169 @@ mod test {
         );
         Ok(())
     }
+
+    #[test]
+    fn multibyte() -> Result<()> {
+        assert_eq!(
+            parse_string("(name café)\n(é)")?,
+            vec![
+                ConfigValue::Pair(
+                    "name".to_string(),
+                    Box::new(ConfigValue::Array(vec![ConfigValue::Ident(
+                        "café".to_string()
+                    )]))
+                ),
+                ConfigValue::Pair("é".to_string(), Box::new(ConfigValue::Array(vec![])))
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn large_input() -> Result<()> {
+        let input = (0..10_000)
+            .map(|i| format!("(key{} value{} other{})\n", i, i, i))
+            .collect::<String>();
+        let start = std::time::Instant::now();
+        assert_eq!(parse_string(input)?.len(), 10_000);
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        Ok(())
+    }
+
+    #[test]
+    fn nested_arrays() -> Result<()> {
+        let input = "(matrix (1 2) (3 4))\n(empty ())";
+        let parsed = parse_string(input)?;
+        assert_eq!(
+            parsed[0],
+            ConfigValue::Pair(
+                "matrix".to_string(),
+                Box::new(ConfigValue::Array(vec![
+                    ConfigValue::Array(vec![
+                        ConfigValue::Ident("1".to_string()),
+                        ConfigValue::Ident("2".to_string())
+                    ]),
+                    ConfigValue::Array(vec![
+                        ConfigValue::Ident("3".to_string()),
+                        ConfigValue::Ident("4".to_string())
+                    ])
+                ]))
+            )
+        );
+        let printed = parsed
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>()
+            .join("\n");
+        assert_eq!(printed, input);
+        assert_eq!(parse_string(printed)?, parsed);
+        Ok(())
+    }
+
+    #[test]
+    fn typed_accessors() -> Result<()> {
+        let vals = parse_string("(name foo)\n(flags -a -b)\n(on yes)\n(off false)\n(nested (a))")?;
+        assert_eq!(get_string(&vals, "name")?, Some("foo".to_string()));
+        assert_eq!(get_string(&vals, "missing")?, None);
+        assert!(get_string(&vals, "flags").is_err());
+        assert_eq!(
+            get_list(&vals, "flags")?,
+            Some(vec!["-a".to_string(), "-b".to_string()])
+        );
+        assert_eq!(get_list(&vals, "missing")?, None);
+        assert!(get_list(&vals, "nested").is_err());
+        assert_eq!(get_bool(&vals, "on")?, Some(true));
+        assert_eq!(get_bool(&vals, "off")?, Some(false));
+        assert_eq!(get_bool(&vals, "missing")?, None);
+        assert!(get_bool(&vals, "name").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn command_substitution() -> Result<()> {
+        let vals =
+            parse_string("(flags -O2 $(pkg-config --cflags (x)) `llvm-config --cflags`)\n(v $x)")?;
+        assert_eq!(
+            get_list(&vals, "flags")?,
+            Some(vec![
+                "-O2".to_string(),
+                "$(pkg-config --cflags (x))".to_string(),
+                "`llvm-config --cflags`".to_string()
+            ])
+        );
+        assert_eq!(get_string(&vals, "v")?, Some("$x".to_string()));
+        let quoted = parse_string("(flags \"$(date)\")")?;
+        assert_eq!(
+            get_list(&quoted, "flags")?,
+            Some(vec!["$(date)".to_string()])
+        );
+        let e = parse_string("(flags $(echo (x)").err().unwrap();
+        assert_eq!(e.0, "line 1: Unterminated command substitution.");
+        Ok(())
+    }
+
+    #[test]
+    fn quoted_single_string() -> Result<()> {
+        let vals = parse_string("(name \"My App\")\n(bare My App)")?;
+        assert_eq!(get_string(&vals, "name")?, Some("My App".to_string()));
+        let e = get_string(&vals, "bare").err().unwrap();
+        assert_eq!(e.0, "Key `bare` must be a single string.");
+        Ok(())
+    }
+
+    #[test]
+    fn booleans() -> Result<()> {
+        for (word, expected) in [
+            ("true", true),
+            ("yes", true),
+            ("on", true),
+            ("1", true),
+            ("false", false),
+            ("no", false),
+            ("off", false),
+            ("0", false),
+        ] {
+            assert_eq!(as_bool(&ConfigValue::Ident(word.to_string()))?, expected);
+            let vals = parse_string(format!("(werror {})", word))?;
+            assert_eq!(get_bool(&vals, "werror")?, Some(expected));
+        }
+        let e = as_bool(&ConfigValue::Ident("maybe".to_string()))
+            .err()
+            .unwrap();
+        assert!(e.0.starts_with("`maybe` is not a boolean."));
+        let vals = parse_string("(werror yes no)")?;
+        assert!(get_bool(&vals, "werror").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn quoted() -> Result<()> {
+        let vals = parse_string("(hook \"echo \\\"a b\\\"\" c)\n(hook \"(x)\")")?;
+        assert_eq!(
+            get_all(&vals, "hook")?,
+            vec![
+                "echo \"a b\"".to_string(),
+                "c".to_string(),
+                "(x)".to_string()
+            ]
+        );
+        assert_eq!(parse_string(vals[0].to_string())?[0], vals[0]);
+        assert!(parse_string("(hook \"a)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn platforms() -> Result<()> {
+        let input =
+            "(flags -a)\n(platform linux (flags -b) (libs rt))\n(platform macos (flags -c))";
+        let linux = resolve_platforms(parse_string(input)?, "linux")?;
+        assert_eq!(get_list(&linux, "flags")?, Some(vec!["-b".to_string()]));
+        assert_eq!(get_list(&linux, "libs")?, Some(vec!["rt".to_string()]));
+        let windows = resolve_platforms(parse_string(input)?, "windows")?;
+        assert_eq!(get_list(&windows, "flags")?, Some(vec!["-a".to_string()]));
+        assert_eq!(get_list(&windows, "libs")?, None);
+        assert!(resolve_platforms(parse_string("(platform beos (flags -a))")?, "linux").is_err());
+        assert!(resolve_platforms(parse_string("(platform linux flags)")?, "linux").is_err());
+        Ok(())
+    }
 }
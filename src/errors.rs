@@ -8,3 +8,10 @@ macro_rules! error {
         $crate::errors::Result::Err($crate::errors::Error(format_args!($($arg)*).to_string()))
     };
 }
+
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        eprintln!("ketch: warning: {}", format_args!($($arg)*))
+    };
+}
@@ -0,0 +1,79 @@
+use std::fmt::{self, Display, Formatter};
+
+/// A minimal JSON value, used for machine-readable output.
+pub enum Json {
+    Str(String),
+    Num(u64),
+    Bool(bool),
+    Obj(Vec<(&'static str, Json)>),
+    Arr(Vec<Json>),
+}
+impl Json {
+    pub fn str(s: impl ToString) -> Self {
+        Self::Str(s.to_string())
+    }
+}
+impl Display for Json {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Str(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Self::Num(n) => write!(f, "{}", n),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Obj(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", Json::str(key), value)?;
+                }
+                write!(f, "}}")
+            }
+            Self::Arr(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            Json::Obj(vec![
+                ("event", Json::str("error")),
+                ("message", Json::str("a \"b\"\n\\\u{1}")),
+                ("count", Json::Num(3)),
+                ("ok", Json::Bool(true)),
+                ("list", Json::Arr(vec![Json::Num(1), Json::str("x")])),
+                ("empty", Json::Arr(vec![])),
+            ])
+            .to_string(),
+            r#"{"event":"error","message":"a \"b\"\n\\\u0001","count":3,"ok":true,"list":[1,"x"],"empty":[]}"#
+        );
+    }
+}
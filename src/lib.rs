@@ -0,0 +1,18 @@
+//! The ketch build pipeline, as driven by the `ketch` binary: reading a
+//! ketchfile, creating projects and building them.
+
+mod config;
+pub mod errors;
+mod json;
+mod project;
+
+pub use config::{parse_file, parse_string, ConfigValue};
+pub use project::{
+    manager::{
+        add_dependency, build_project, check_project, compile_command, create_project,
+        doctor_project, explain_config, init_project, project_graph, read_project,
+        remove_dependency, BuildOptions, BuildReport, Emit, OutputFormat,
+    },
+    parse_standard, BuildScript, CompilerFamily, Dependency, Project, ProjectBuilder, ProjectType,
+    Standard, Std,
+};
@@ -1,11 +1,18 @@
-mod config;
-mod errors;
-mod project;
+mod completions;
+/* Also built into the library, which uses the rest of it. */
+#[allow(dead_code)]
+mod json;
 
-use errors::Result;
-use project::{manager::{build_project, create_project}, ProjectType};
-use std::{process::exit, env};
 use getopt_rs::getopt;
+use json::Json;
+use ketch::{
+    add_dependency, build_project, check_project, compile_command, create_project, doctor_project,
+    error,
+    errors::{Error, Result},
+    explain_config, init_project, parse_standard, project_graph, read_project, remove_dependency,
+    BuildOptions, Emit, OutputFormat, ProjectType,
+};
+use std::{env, process::exit, time::Duration};
 
 fn main() -> ! {
     match try_main() {
@@ -18,36 +25,178 @@ fn main() -> ! {
 fn help(command: Option<&str>) {
     if let Some(command) = command {
         match command {
-            "new" => println!("Usage: ketch new NAME [OPTION]...
+            "new" => println!(
+                "Usage: ketch new NAME [OPTION]...
 OPTIONS
     -s, --static    Create a static library project.
     -S, --shared    Create a shared library project.
-        --help      Display this help and exit."),
-            "build" => println!("Usage: ketch build [OPTION]
+        --git       Initialise a git repository.
+        --help      Display this help and exit."
+            ),
+            "build" => println!(
+                "Usage: ketch build [OPTION]... [PATH]
+       ketch build --print-flags [FILE]
 OPTIONS
-    --release   Build with optimisation flags.
-    --help      Display this help and exit."),
+    -k, --keep-going    Keep compiling the other files after a failure.
+        --release       Build with the `release-flags` (`-O3 -DNDEBUG` by
+                        default).
+        --no-debug      Build without the `debug-flags` (`-g -O0` by default)
+                        added when not building with --release.
+        --opt-level LEVEL
+                        Compile with `-OLEVEL` instead of the configured and
+                        release optimisation flags. LEVEL is one of 0, 1, 2, 3,
+                        s, z or g.
+        --log FILE      Also write the build transcript to FILE.
+        --werror        Treat warnings as errors.
+        --no-werror     Do not treat warnings as errors.
+        --timings       Report how long each file took to compile.
+        --explain       Explain why each file is compiled or skipped, list
+                        the files with the most warnings, and report the
+                        flags dropped by --no-default-flags.
+        --format FMT    Report progress as `text` (default) or as
+                        newline-delimited `json` events.
+        --strict        Turn warnings about the configuration into errors.
+                        Implies --check-flags.
+        --check-flags   Warn about flags that do not start with `-`.
+        --both          Build both a static and a shared library.
+        --compiler CC   Compile and link with CC instead of the configured
+                        compiler.
+        --std STD       Compile with the STD standard instead of the
+                        configured one.
+        --print-flags   Print the compiler invocation for FILE, or the flags
+                        shared by all files, instead of building.
+        --explain-config
+                        Print every setting with its value and where it comes
+                        from (default, ketchfile, environment or command
+                        line), instead of building.
+        --manifest PATH Read the configuration from PATH instead of
+                        `./ketchfile`, building in its directory.
+        --fail-verbose  Only show the commands that fail, with their output.
+        --linker LD     Link with LD (`bfd`, `gold`, `lld` or `mold`) instead of
+                        the configured linker.
+        --max-errors N  Stop the compiler after N errors in a file and, with
+                        --keep-going, the build after N failed files.
+        --unity         Compile the C sources as a single translation unit.
+        --output-dir DIR
+                        Put the objects and generated files in DIR instead of
+                        `$KETCH_BUILD_DIR` or `./build`.
+        --emit KIND     Produce the linked artifacts with `bin` (default), or
+                        only the object files with `obj`, printing their paths.
+        --no-default-flags
+                        Compile without the default or configured flags,
+                        keeping only those the build requires. With
+                        --explain, print the flags dropped.
+        --help          Display this help and exit.
+
+Given a PATH under a source directory, only the sources under it are compiled;
+the artifacts are linked with the existing objects of the others."
+            ),
+            "init" => println!(
+                "Usage: ketch init [OPTION]...
+OPTIONS
+        --force     Overwrite an existing `ketchfile`.
+        --help      Display this help and exit."
+            ),
+            "info" => println!(
+                "Usage: ketch info [OPTION]...
+OPTIONS
+        --manifest PATH Read the configuration from PATH instead of
+                        `./ketchfile`.
+        --help          Display this help and exit."
+            ),
+            "check" => println!(
+                "Usage: ketch check [OPTION]...
+Run the `pre-build` hooks, and the build script if it runs before compilation,
+then compile each source with `-fsyntax-only`.
+OPTIONS
+    -k, --keep-going    Keep checking the other files after a failure.
+        --fail-verbose  Only show the commands that fail, with their output.
+        --manifest PATH Read the configuration from PATH instead of
+                        `./ketchfile`, checking in its directory.
+        --help          Display this help and exit."
+            ),
+            "doctor" => println!(
+                "Usage: ketch doctor [OPTION]...
+Check that the ketchfile parses, that `src` exists and that the compiler, the
+archiver, git and the build script interpreter are installed.
+OPTIONS
+        --manifest PATH Read the configuration from PATH instead of
+                        `./ketchfile`.
+        --help          Display this help and exit."
+            ),
+            "add" => println!(
+                "Usage: ketch add [OPTION]... [SOURCE:]OWNER/REPO[@TAG]
+Declare a dependency in the ketchfile. SOURCE defaults to `github`.
+OPTIONS
+        --manifest PATH Edit PATH instead of `./ketchfile`.
+        --help          Display this help and exit."
+            ),
+            "remove" => println!(
+                "Usage: ketch remove [OPTION]... [OWNER/]REPO
+Remove a dependency from the ketchfile and delete `deps/REPO`.
+OPTIONS
+        --manifest PATH Edit PATH instead of `./ketchfile`.
+        --help          Display this help and exit."
+            ),
+            "graph" => println!(
+                "Usage: ketch graph [OPTION]...
+Print the graph of the installed dependencies, direct or not, in Graphviz DOT.
+OPTIONS
+        --format FMT    Print the graph as `dot` (default), or as a `json`
+                        object of `nodes` and `edges`.
+        --manifest PATH Read the configuration from PATH instead of
+                        `./ketchfile`.
+        --help          Display this help and exit."
+            ),
             _ => unreachable!(),
         }
     } else {
-        println!("Usage: ketch COMMAND [OPTION]...
+        println!(
+            "Usage: ketch [-C DIR] [--timeout SECONDS] COMMAND [OPTION]...
 COMMANDS
     new PATH    Create a new ketch project at PATH.
+    init        Create a `ketchfile` for the sources in the current directory.
     build       Build the project according to the `ketchfile`.
+    check       Check that the project compiles, without building it.
+    info        Display the project configuration and metadata.
+    graph       Print the dependency graph in Graphviz DOT.
+    add DEP     Declare the dependency DEP in the `ketchfile`.
+    remove DEP  Remove the dependency DEP and its installation.
+    doctor      Check that the tools a build needs are installed.
+    completions SHELL
+                Print the completion script for SHELL (bash, zsh or fish).
 
 OPTIONS
-    --help      Display this help and exit.
-    --version   Display version information and exit.");
+    -C, --directory DIR    Run in DIR instead of the current directory.
+        --timeout SECONDS  Kill the commands ketch runs, such as the compiler,
+                           after SECONDS and fail.
+        --help             Display this help and exit.
+        --version [--format FMT]
+                           Display version information and exit, as text
+                           (default) or as a `json` object also giving the
+                           rustc version and target ketch was built with."
+        );
     }
 }
 
-fn handle_new(args: &mut Vec<String>) -> Result<()> {
+fn handle_new(args: &mut Vec<String>, timeout: Option<Duration>) -> Result<()> {
     args.remove(0);
     let mut ptype = ProjectType::Binary;
-    while let Some((opt, _)) = getopt(args, "Ss\n", &[('S', "shared"), ('s', "static"), ('\n', "help")]) {
+    let mut git = false;
+    while let Some((opt, _)) = getopt(
+        args,
+        "Ss\n\x0b",
+        &[
+            ('S', "shared"),
+            ('s', "static"),
+            ('\n', "help"),
+            ('\x0b', "git"),
+        ],
+    ) {
         match opt {
             'S' => ptype = ProjectType::Shared,
             's' => ptype = ProjectType::Static,
+            '\x0b' => git = true,
             '\n' => {
                 help(Some("new"));
                 return Ok(());
@@ -58,37 +207,367 @@ fn handle_new(args: &mut Vec<String>) -> Result<()> {
     if args.len() < 2 {
         error!("Missing argument: NAME.")
     } else {
-        create_project(&args[1], ptype)?;
+        create_project(&args[1], ptype, git, timeout)?;
         Ok(())
     }
 }
-fn handle_build(args: &mut Vec<String>) -> Result<()> {
+fn handle_init(args: &mut Vec<String>) -> Result<()> {
+    args.remove(0);
+    let mut force = false;
+    while let Some((opt, _)) = getopt(args, "\n\x0b", &[('\n', "help"), ('\x0b', "force")]) {
+        match opt {
+            '\x0b' => force = true,
+            '\n' => {
+                help(Some("init"));
+                return Ok(());
+            }
+            _ => exit(1),
+        }
+    }
+    init_project(force)?;
+    Ok(())
+}
+fn handle_build(args: &mut Vec<String>, mut options: BuildOptions) -> Result<()> {
     args.remove(0);
-    let mut release = false;
-    while let Some((opt, _)) = getopt(args, "\n\r", &[('\n', "help"), ('\r', "release")]) {
+    let mut flags_only = false;
+    let mut config_only = false;
+    while let Some((opt, arg)) = getopt(
+        args,
+        "\n\rk\t:\x0b\x0c\x0e\x0f\x10\x11:\x12\x13\x14:\x15:\x16:\x17\x18:\x19\x1a\x1b:\x1c\x1d\x1e:\x1f:\x01\x02:",
+        &[
+            ('\n', "help"),
+            ('\r', "release"),
+            ('k', "keep-going"),
+            ('\t', "log"),
+            ('\x0b', "werror"),
+            ('\x0c', "no-werror"),
+            ('\x0e', "timings"),
+            ('\x0f', "print-flags"),
+            ('\x10', "explain"),
+            ('\x11', "format"),
+            ('\x12', "strict"),
+            ('\x13', "both"),
+            ('\x14', "compiler"),
+            ('\x15', "std"),
+            ('\x16', "manifest"),
+            ('\x17', "no-default-flags"),
+            ('\x18', "emit"),
+            ('\x19', "fail-verbose"),
+            ('\x1a', "check-flags"),
+            ('\x1b', "opt-level"),
+            ('\x1c', "no-debug"),
+            ('\x1d', "explain-config"),
+            ('\x1e', "output-dir"),
+            ('\x1f', "linker"),
+            ('\x01', "unity"),
+            ('\x02', "max-errors"),
+        ],
+    ) {
         match opt {
             '\n' => {
                 help(Some("build"));
                 return Ok(());
             }
-            '\r' => release = true,
+            '\r' => options.release = true,
+            'k' => options.keep_going = true,
+            '\t' => options.log = arg,
+            '\x0b' => options.werror = Some(true),
+            '\x0c' => options.werror = Some(false),
+            '\x0e' => options.timings = true,
+            '\x0f' => flags_only = true,
+            '\x1d' => config_only = true,
+            '\x10' => options.explain = true,
+            '\x12' => options.strict = true,
+            '\x13' => options.both = true,
+            '\x14' => options.compiler = arg,
+            '\x15' => options.standard = Some(parse_standard(&arg.unwrap_or_default())?),
+            '\x16' => options.manifest = arg,
+            '\x1e' => options.output_dir = arg,
+            '\x1f' => options.linker = arg,
+            '\x01' => options.unity = true,
+            '\x02' => {
+                options.max_errors = match arg.as_deref().unwrap_or_default().parse() {
+                    Ok(count) if count > 0 => Some(count),
+                    _ => return error!("`{}` is not a valid error count. Expected a positive integer.", arg.unwrap_or_default()),
+                }
+            }
+            '\x17' => options.no_default_flags = true,
+            '\x1c' => options.no_debug = true,
+            '\x1a' => options.check_flags = true,
+            '\x1b' => options.opt_level = Some(opt_level_flag(&arg.unwrap_or_default())?),
+            '\x19' => options.fail_verbose = true,
+            '\x18' => {
+                options.emit = match arg.as_deref() {
+                    Some("bin") => Emit::Bin,
+                    Some("obj") => Emit::Obj,
+                    x => return error!("`{}` is not a valid output kind. Available kinds: bin, obj.", x.unwrap_or_default()),
+                }
+            }
+            '\x11' => {
+                options.format = match arg.as_deref() {
+                    Some("text") => OutputFormat::Text,
+                    Some("json") => OutputFormat::Json,
+                    x => return error!("`{}` is not a valid output format. Available formats: text, json.", x.unwrap_or_default()),
+                }
+            }
+            _ => exit(1),
+        }
+    }
+    if config_only {
+        let rows = explain_config(&options)?;
+        let width = rows.iter().map(|(key, _, _)| key.len()).max().unwrap_or(0);
+        for (key, value, source) in rows {
+            match value.as_str() {
+                "" => println!("{:<width$}  [{}]", key, source, width = width),
+                value => println!("{:<width$}  {} [{}]", key, value, source, width = width),
+            }
+        }
+        Ok(())
+    } else if flags_only {
+        println!(
+            "{}",
+            compile_command(&options, args.get(1).map(|s| s.as_str()))?
+        );
+        Ok(())
+    } else {
+        options.path = args.get(1).cloned();
+        build_project(&options).map(|_| ())
+    }
+}
+fn handle_check(args: &mut Vec<String>, mut options: BuildOptions) -> Result<()> {
+    args.remove(0);
+    while let Some((opt, arg)) = getopt(
+        args,
+        "k\n\x16:\x19",
+        &[
+            ('k', "keep-going"),
+            ('\n', "help"),
+            ('\x16', "manifest"),
+            ('\x19', "fail-verbose"),
+        ],
+    ) {
+        match opt {
+            'k' => options.keep_going = true,
+            '\x16' => options.manifest = arg,
+            '\x19' => options.fail_verbose = true,
+            '\n' => {
+                help(Some("check"));
+                return Ok(());
+            }
+            _ => exit(1),
+        }
+    }
+    check_project(&options)
+}
+fn handle_info(args: &mut Vec<String>, mut options: BuildOptions) -> Result<()> {
+    args.remove(0);
+    while let Some((opt, arg)) = getopt(args, "\n\x16:", &[('\n', "help"), ('\x16', "manifest")]) {
+        match opt {
+            '\x16' => options.manifest = arg,
+            '\n' => {
+                help(Some("info"));
+                return Ok(());
+            }
             _ => exit(1),
         }
     }
-    build_project(release)
+    println!("{}", read_project(&options)?);
+    Ok(())
+}
+fn handle_doctor(args: &mut Vec<String>, mut options: BuildOptions) -> Result<()> {
+    args.remove(0);
+    while let Some((opt, arg)) = getopt(args, "\n\x16:", &[('\n', "help"), ('\x16', "manifest")]) {
+        match opt {
+            '\x16' => options.manifest = arg,
+            '\n' => {
+                help(Some("doctor"));
+                return Ok(());
+            }
+            _ => exit(1),
+        }
+    }
+    doctor_project(&options)
+}
+fn handle_add(args: &mut Vec<String>, mut options: BuildOptions) -> Result<()> {
+    args.remove(0);
+    while let Some((opt, arg)) = getopt(args, "\n\x16:", &[('\n', "help"), ('\x16', "manifest")]) {
+        match opt {
+            '\x16' => options.manifest = arg,
+            '\n' => {
+                help(Some("add"));
+                return Ok(());
+            }
+            _ => exit(1),
+        }
+    }
+    match args.get(1) {
+        Some(spec) => add_dependency(&options, spec).map(|_| ()),
+        None => error!("Missing argument: DEP."),
+    }
+}
+fn handle_remove(args: &mut Vec<String>, mut options: BuildOptions) -> Result<()> {
+    args.remove(0);
+    while let Some((opt, arg)) = getopt(args, "\n\x16:", &[('\n', "help"), ('\x16', "manifest")]) {
+        match opt {
+            '\x16' => options.manifest = arg,
+            '\n' => {
+                help(Some("remove"));
+                return Ok(());
+            }
+            _ => exit(1),
+        }
+    }
+    match args.get(1) {
+        Some(query) => remove_dependency(&options, query).map(|_| ()),
+        None => error!("Missing argument: DEP."),
+    }
+}
+fn handle_graph(args: &mut Vec<String>, mut options: BuildOptions) -> Result<()> {
+    args.remove(0);
+    while let Some((opt, arg)) = getopt(
+        args,
+        "\n\x11:\x16:",
+        &[('\n', "help"), ('\x11', "format"), ('\x16', "manifest")],
+    ) {
+        match opt {
+            '\x16' => options.manifest = arg,
+            '\x11' => {
+                options.format = match arg.as_deref() {
+                    Some("dot") => OutputFormat::Text,
+                    Some("json") => OutputFormat::Json,
+                    x => {
+                        return error!(
+                            "`{}` is not a valid graph format. Available formats: dot, json.",
+                            x.unwrap_or_default()
+                        )
+                    }
+                }
+            }
+            '\n' => {
+                help(Some("graph"));
+                return Ok(());
+            }
+            _ => exit(1),
+        }
+    }
+    println!("{}", project_graph(&options)?);
+    Ok(())
+}
+/// The compiler flag for `--opt-level LEVEL`.
+fn opt_level_flag(level: &str) -> Result<String> {
+    match level {
+        "0" | "1" | "2" | "3" | "s" | "z" | "g" => Ok(format!("-O{}", level)),
+        x => error!(
+            "`{}` is not a valid optimisation level. Available levels: 0, 1, 2, 3, s, z, g.",
+            x
+        ),
+    }
 }
 fn try_main() -> Result<()> {
     let mut args = env::args().collect::<Vec<String>>();
-    
-    if let Some(cmd) = args.iter().nth(1) {
+
+    /* Global options, given before the command. */
+    let mut options = BuildOptions::default();
+    while let Some(opt) = args.get(1).cloned() {
+        let (name, value) = match opt.as_str() {
+            "-C" | "--directory" => ("-C", None),
+            "--timeout" => ("--timeout", None),
+            _ => {
+                if let Some(dir) = opt
+                    .strip_prefix("--directory=")
+                    .or_else(|| opt.strip_prefix("-C"))
+                {
+                    ("-C", Some(dir.to_string()))
+                } else if let Some(seconds) = opt.strip_prefix("--timeout=") {
+                    ("--timeout", Some(seconds.to_string()))
+                } else {
+                    break;
+                }
+            }
+        };
+        args.remove(1);
+        let value = match value {
+            Some(value) => value,
+            None if args.len() > 1 => args.remove(1),
+            None => return error!("Missing argument for `{}`.", opt),
+        };
+        if name == "-C" {
+            env::set_current_dir(&value)
+                .map_err(|e| Error(format!("Failed to enter directory: {}: {}.", value, e)))?;
+        } else {
+            match value.parse() {
+                Ok(seconds) => options.timeout = Some(Duration::from_secs(seconds)),
+                Err(_) => {
+                    return error!(
+                        "`{}` is not a valid timeout. Expected a number of seconds.",
+                        value
+                    )
+                }
+            }
+        }
+    }
+
+    /* `--format` may also be given before `--version`. */
+    if args.get(1).is_some_and(|arg| arg.starts_with("--format")) {
+        if let Some(i) = args.iter().position(|arg| arg == "--version") {
+            let version = args.remove(i);
+            args.insert(1, version);
+        }
+    }
+    if let Some(cmd) = args.get(1) {
         match cmd.as_str() {
             "--help" => help(None),
-            "--version" => println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
-            "new" => return handle_new(&mut args),
-            "build" => return handle_build(&mut args),
-            x => return error!("`{}` is not a valid commands. Type `ketch --help` for a list of commands.", x),
+            "--version" => match args[2..].iter().map(|s| s.as_str()).collect::<Vec<&str>>()[..] {
+                [] | ["--format", "text"] | ["--format=text"] => {
+                    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+                }
+                ["--format", "json"] | ["--format=json"] => println!(
+                    "{}",
+                    Json::Obj(vec![
+                        ("name", Json::str(env!("CARGO_PKG_NAME"))),
+                        ("version", Json::str(env!("CARGO_PKG_VERSION"))),
+                        ("rustc", Json::str(env!("KETCH_RUSTC_VERSION"))),
+                        ("target", Json::str(env!("KETCH_TARGET"))),
+                    ])
+                ),
+                _ => return error!("Usage: ketch --version [--format text|json]."),
+            },
+            "new" => return handle_new(&mut args, options.timeout),
+            "init" => return handle_init(&mut args),
+            "build" => return handle_build(&mut args, options),
+            "check" => return handle_check(&mut args, options),
+            "info" => return handle_info(&mut args, options),
+            "doctor" => return handle_doctor(&mut args, options),
+            "add" => return handle_add(&mut args, options),
+            "remove" => return handle_remove(&mut args, options),
+            "graph" => return handle_graph(&mut args, options),
+            "completions" => match args.get(2) {
+                Some(shell) => print!("{}", completions::script(shell)?),
+                None => return error!("Missing argument: SHELL."),
+            },
+            x => {
+                return error!(
+                    "`{}` is not a valid commands. Type `ketch --help` for a list of commands.",
+                    x
+                )
+            }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn opt_levels() {
+        for level in ["0", "1", "2", "3", "s", "z", "g"] {
+            assert_eq!(opt_level_flag(level).unwrap(), format!("-O{}", level));
+        }
+        for level in ["4", "fast", "-O2", ""] {
+            assert!(opt_level_flag(level).is_err());
+        }
+    }
+}
@@ -1,14 +1,27 @@
 use crate::{
-    config::parse_file,
+    config::{
+        find_val, get_all, get_list, parse_file, parse_string, resolve_platforms, ConfigValue,
+    },
     error,
     errors::{Error, Result},
-    project::{BuildScript, Project, ProjectType},
+    json::Json,
+    project::{
+        compiler_family, compiler_version, is_older, BuildScript, CompilerFamily, Dependency,
+        Project, ProjectType, Standard,
+    },
+    warning,
 };
 use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    env, fmt,
     fs::{self, File},
-    io::Write,
-    path::Path,
-    process::Command,
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
 };
 
 const POSSIBLE_SCRIPTS: [(&str, &str); 3] = [
@@ -17,32 +30,45 @@ const POSSIBLE_SCRIPTS: [(&str, &str); 3] = [
     ("./build.py", "python3"),
 ];
 
-fn run_build_script() -> Result<()> {
+/// The environment exposed to build scripts and hooks.
+fn script_env(project: &Project) -> Vec<(&'static str, String)> {
+    let build_dir = env::current_dir()
+        .map(|dir| dir.join(&project.build_dir))
+        .unwrap_or_else(|_| PathBuf::from(&project.build_dir));
+    vec![
+        ("KETCH_NAME", project.name.clone()),
+        ("KETCH_VERSION", project.version.clone()),
+        ("KETCH_TYPE", project.ptype.to_string()),
+        ("KETCH_CC", project.compiler.clone()),
+        ("KETCH_CXX", project.cxx_compiler.clone()),
+        ("KETCH_BUILDDIR", build_dir.to_string_lossy().to_string()),
+    ]
+}
+
+/// The command running the build script found in `dir`, with the project
+/// environment set.
+fn build_script_command(dir: &Path, project: &Project) -> Result<Command> {
     let mut build_script = None;
     for (script, interpreter) in POSSIBLE_SCRIPTS {
-        if Path::new(script).exists() {
-            build_script = Some((script, interpreter));
+        let path = dir.join(Path::new(script).file_name().unwrap_or_default());
+        if path.exists() {
+            build_script = Some((path, interpreter));
         }
     }
-    if let Some((interpreter, script)) = build_script {
-        println!("{} {}", interpreter, script);
-        if !Command::new(interpreter)
-            .arg(script)
-            .status()
-            .map_err(|e| {
-                Error(format!(
-                    "Failed to summon command: `{} {}`: {}",
-                    interpreter,
-                    script,
-                    e
-                ))
-            })?
-            .success()
-        {
-            error!("Aborting at first failed command.")
-        } else {
-            Ok(())
+    if let Some((script, interpreter)) = build_script {
+        if let Err(e) = File::open(&script) {
+            return error!("Failed to read build script: {}: {}.", script.display(), e);
+        }
+        if find_program(interpreter).is_none() {
+            return error!(
+                "Build script interpreter `{}` was not found in PATH. Install it to run `{}`.",
+                interpreter,
+                script.display()
+            );
         }
+        let mut command = Command::new(interpreter);
+        command.arg(script).envs(script_env(project));
+        Ok(command)
     } else {
         error!(
             "No buildscript found. Possible build scripts: {}.",
@@ -58,150 +84,3245 @@ fn run_build_script() -> Result<()> {
     }
 }
 
-pub fn create_project(name: &str, ptype: ProjectType) -> Result<Project> {
-    let src = format!("{}/src", name);
-    fs::create_dir_all(&src)
-        .map_err(|e| Error(format!("Failed to create directory: {}: {}.", src, e)))?;
+/// Runs the build script, echoing it through `log`. When emitting JSON, the
+/// script's stdout goes to stderr so that it doesn't mix with the events.
+fn run_build_script(project: &Project, log: &mut BuildLog) -> Result<()> {
+    let mut command = build_script_command(Path::new("."), project)?;
+    let echo = echo(&command);
+    log.println(&echo)?;
+    log.event("script", vec![("command", Json::str(&echo))])?;
+    if log.format == OutputFormat::Json {
+        command.stdout(Stdio::from(io::stderr()));
+    }
+    let child = command
+        .spawn()
+        .map_err(|e| Error(format!("Failed to summon command: `{}`: {}", echo, e)))?;
+    if !wait(child, log.timeout, &echo)?.status.success() {
+        error!("Aborting at first failed command.")
+    } else {
+        Ok(())
+    }
+}
 
-    let build = format!("{}/build", name);
-    fs::create_dir_all(&build)
-        .map_err(|e| Error(format!("Failed to create directory: {}: {}.", build, e)))?;
+/// Waits for `child` to exit, collecting its output if it was piped. Kills it
+/// and fails if it runs for longer than `timeout`.
+fn wait(mut child: Child, timeout: Option<Duration>, echo: &str) -> Result<Output> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => {
+            return child
+                .wait_with_output()
+                .map_err(|e| Error(format!("Failed to wait for command: `{}`: {}", echo, e)))
+        }
+    };
+    /* Drain the pipes while polling, so that a chatty child doesn't block. */
+    fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut bytes = vec![];
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut bytes);
+            }
+            bytes
+        })
+    }
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return error!("`{}` timed out after {}s.", echo, timeout.as_secs());
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(10)),
+            Err(e) => return error!("Failed to wait for command: `{}`: {}", echo, e),
+        }
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
 
-    let ketchfile = format!("{}/ketchfile", name);
-    File::create(&ketchfile)
-        .map_err(|e| Error(format!("Failed to create file: {}: {}.", ketchfile, e)))?
-        .write_all(format!("(name {})\n(version 0.1.0)\n(type {})\n", name, match ptype {
-            ProjectType::Binary => "binary",
-            ProjectType::Shared => "shared",
-            ProjectType::Static => "static",
-        }).as_bytes())
-        .map_err(|e| Error(format!("Failed to write file: {}: {}.", ketchfile, e)))?;
+/// `command` as echoed before running it, in `program args` form.
+fn echo(command: &Command) -> String {
+    let mut echo = command.get_program().to_string_lossy().to_string();
+    for arg in command.get_args() {
+        echo.push(' ');
+        echo.push_str(&arg.to_string_lossy());
+    }
+    echo
+}
 
-    let main = format!("{}/main.c", src);
-    File::create(&main)
-        .map_err(|e| Error(format!("Failed to create file: {}: {}.", main, e)))?
-        .write_all(b"#include <stdlib.h>\n\nint\nmain (void)\n{\n  return EXIT_SUCCESS;\n}\n")
-        .map_err(|e| Error(format!("Failed to write file: {}: {}.", main, e)))?;
+/// Resolves `program` the way a shell would, searching `PATH` unless it
+/// already contains a path separator.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    if program.contains(std::path::MAIN_SEPARATOR) || program.contains('/') {
+        let path = PathBuf::from(program);
+        return if path.is_file() { Some(path) } else { None };
+    }
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|path| path.is_file())
+    })
+}
 
-    Project::from_config(parse_file(ketchfile)?)
+/// Writes `contents` to `path` through a temporary file in the same directory,
+/// renamed into place, so that an interrupted write never leaves `path`
+/// truncated.
+fn write_atomic(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    write_atomic_with(path.as_ref(), |file| file.write_all(contents.as_ref()))
 }
 
-pub fn build_project(release: bool) -> Result<()> {
-    let mut project = Project::from_config(parse_file("./ketchfile")?)?;
-    if release {
-        project.flags.push("-O3".to_string());
+fn write_atomic_with(path: &Path, write: impl FnOnce(&mut File) -> io::Result<()>) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let written = File::create(&temp).and_then(|mut file| {
+        write(&mut file)?;
+        file.sync_all()
+    });
+    let renamed = written.and_then(|_| {
+        fs::rename(&temp, path).or_else(|e| {
+            /* Windows may refuse to rename over a file that is in use. */
+            if cfg!(windows) && path.exists() {
+                fs::remove_file(path).and_then(|_| fs::rename(&temp, path))
+            } else {
+                Err(e)
+            }
+        })
+    });
+    if let Err(e) = renamed {
+        let _ = fs::remove_file(&temp);
+        return error!("Failed to write file: {}: {}.", path.display(), e);
     }
+    Ok(())
+}
+
+/// Creates a project named `name` in a new directory of the same name, with a
+/// git repository if `git` is set. `timeout` bounds `git init`.
+pub fn create_project(
+    name: &str,
+    ptype: ProjectType,
+    git: bool,
+    timeout: Option<Duration>,
+) -> Result<Project> {
+    let src = Path::new(name).join("src");
+    fs::create_dir_all(&src).map_err(|e| {
+        Error(format!(
+            "Failed to create directory: {}: {}.",
+            src.display(),
+            e
+        ))
+    })?;
+
+    let build = Path::new(name).join("build");
+    fs::create_dir_all(&build).map_err(|e| {
+        Error(format!(
+            "Failed to create directory: {}: {}.",
+            build.display(),
+            e
+        ))
+    })?;
+
+    let ketchfile = Path::new(name).join("ketchfile");
+    write_atomic(
+        &ketchfile,
+        format!("(name {})\n(version 0.1.0)\n(type {})\n", name, ptype),
+    )?;
+
+    let main = src.join("main.c");
+    write_atomic(
+        &main,
+        "#include <stdlib.h>\n\nint\nmain (void)\n{\n  return EXIT_SUCCESS;\n}\n",
+    )?;
 
-    if let BuildScript::Only = project.build_script {
-        return run_build_script();
-    } else if let BuildScript::Before = project.build_script {
-        run_build_script()?;
+    let project = Project::from_config(parse_file(ketchfile.to_string_lossy())?)?;
+
+    let ignore = Path::new(name).join(".gitignore");
+    if !ignore.exists() {
+        write_atomic(&ignore, gitignore(&project))?;
+    }
+    if git {
+        init_git(Path::new(name), timeout)?;
     }
 
-    let files = read_dir("./src/")?
-        .into_iter()
-        .filter(|f| f.ends_with(".c"))
-        .collect::<Vec<String>>();
-    let mut objs = vec![];
+    Ok(project)
+}
 
-    println!(
-        "\x1b[0;32m*\x1b[0m Compiling {}::{} ({} files)...",
-        project.name,
-        project.version,
-        files.len()
+/// The `.gitignore` of a new project: the build and dependencies directories,
+/// and the artifacts.
+fn gitignore(project: &Project) -> String {
+    let mut lines = vec!["build/".to_string(), "deps/".to_string()];
+    lines.extend(
+        artifacts(project)
+            .into_iter()
+            .map(|artifact| format!("/{}", artifact)),
     );
-    for file in files {
-        let mut flags = project.flags.clone();
-        if let ProjectType::Shared = project.ptype {
-            flags.push("-fpic".to_string());
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Runs `git init` in `dir`, the directory of a new project, killing it after
+/// `timeout`. Only warns if git is not installed.
+fn init_git(dir: &Path, timeout: Option<Duration>) -> Result<()> {
+    if find_program("git").is_none() {
+        warning!("`git` was not found in PATH, not initialising a repository.");
+        return Ok(());
+    }
+    let child = Command::new("git")
+        .args(["init", "-q"])
+        .current_dir(dir)
+        .spawn()
+        .map_err(|e| Error(format!("Failed to summon command: `git init -q`: {}", e)))?;
+    if !wait(child, timeout, "git init -q")?.status.success() {
+        return error!(
+            "Failed to initialise a git repository in {}.",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// Writes a ketchfile for the sources already in the current directory, named
+/// after it. The project is a binary if a source defines `main`, a static
+/// library otherwise. An existing ketchfile is only replaced with `force`.
+pub fn init_project(force: bool) -> Result<Project> {
+    let ketchfile = Path::new(".").join("ketchfile");
+    if ketchfile.exists() && !force {
+        return error!("A ketchfile already exists. Use `--force` to overwrite it.");
+    }
+    let cwd = env::current_dir()
+        .map_err(|e| Error(format!("Failed to get current directory: {}.", e)))?;
+    let name = match cwd.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => return error!("Failed to infer a project name from {}.", cwd.display()),
+    };
+    if !Path::new(SOURCE_DIR).is_dir() {
+        warning!("No `src` directory, creating an empty one. Sources elsewhere can be listed in key `source-dirs`.");
+        fs::create_dir_all(SOURCE_DIR).map_err(|e| {
+            Error(format!(
+                "Failed to create directory: {}: {}.",
+                SOURCE_DIR, e
+            ))
+        })?;
+    }
+    let mut ptype = ProjectType::Static;
+    for file in source_files(SOURCE_DIR)? {
+        if defines_main(&fs::read_to_string(&file).unwrap_or_default()) {
+            ptype = ProjectType::Binary;
+            break;
         }
-        flags.push(format!("-std={}", project.standard));
-        flags.extend(vec!["-c".to_string(), file.clone(), "-o".to_string()]);
-        let built = format!(
-            "./build/{}",
-            file[6..] /* Skip `./src/` prefix */
-                .replace("/", "_")
-                .replace(".c", ".o")
-        );
-        objs.push(built.to_string());
-        flags.push(built);
-        println!("{} {}", &project.compiler, flags.join(" "));
-        let status = Command::new(&project.compiler)
-            .args(&flags)
-            .status()
-            .map_err(|e| {
+    }
+    write_atomic(
+        &ketchfile,
+        format!(
+            "(name {})\n(version 0.1.0)\n(type {})\n",
+            ConfigValue::Ident(name),
+            ptype
+        ),
+    )?;
+    Project::from_config(parse_file(ketchfile.to_string_lossy())?)
+}
+
+/// Whether `source` seems to define or declare a `main` function.
+fn defines_main(source: &str) -> bool {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    source.match_indices("main").any(|(i, _)| {
+        !source[..i].ends_with(is_ident) && source[i + 4..].trim_start().starts_with('(')
+    })
+}
+
+/// What `build_project` did, for callers that need more than success.
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    /// The sources compiled, in order.
+    pub compiled: Vec<String>,
+    /// The sources whose objects were up to date.
+    pub skipped: Vec<String>,
+    /// The libraries and executables produced.
+    pub artifacts: Vec<String>,
+    pub duration: Duration,
+    /// Whether C++ objects took part, here or in a dependency, so that the
+    /// projects linking it use the C++ compiler.
+    pub cxx: bool,
+}
+
+/// Command line options that affect `build_project`.
+#[derive(Clone, Default)]
+pub struct BuildOptions {
+    pub release: bool,
+    pub keep_going: bool,
+    pub log: Option<String>,
+    pub werror: Option<bool>,
+    pub timings: bool,
+    pub explain: bool,
+    pub format: OutputFormat,
+    pub strict: bool,
+    pub both: bool,
+    pub compiler: Option<String>,
+    pub standard: Option<Standard>,
+    pub manifest: Option<String>,
+    pub no_default_flags: bool,
+    /// Leaves out the `debug-flags` of a non-release build.
+    pub no_debug: bool,
+    pub emit: Emit,
+    /// Kills the commands running for longer than this.
+    pub timeout: Option<Duration>,
+    pub fail_verbose: bool,
+    /// Warns about flags missing their leading dash, as `--strict` does.
+    pub check_flags: bool,
+    /// An `-O` flag, such as `-O2`, replacing those of the configuration and
+    /// `--release`.
+    pub opt_level: Option<String>,
+    /// Only compiles the sources under this path, linking the existing objects
+    /// of the others.
+    pub path: Option<String>,
+    /// The build directory, taking precedence over `KETCH_BUILD_DIR`.
+    pub output_dir: Option<String>,
+    /// The linker, replacing the `linker` key.
+    pub linker: Option<String>,
+    /// Makes a unity build, as the `unity` key does.
+    pub unity: bool,
+    /// Caps the errors the compiler reports per file, and the failed files
+    /// under `keep_going`.
+    pub max_errors: Option<usize>,
+    /// Builds the project as a static library, as done for dependencies.
+    library: bool,
+}
+
+/// How `build_project` reports its progress.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    /// Newline-delimited JSON events on stdout.
+    Json,
+}
+
+/// What `build_project` produces.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Emit {
+    /// The linked binary or libraries.
+    #[default]
+    Bin,
+    /// Only the object files, skipping the link.
+    Obj,
+}
+
+/// Prints the build progress in the chosen format and mirrors it into an
+/// optional log file.
+struct BuildLog {
+    file: Option<(String, File)>,
+    format: OutputFormat,
+    /// How long the commands run through the log may take.
+    timeout: Option<Duration>,
+    /// Whether to only show the commands that failed, and their output.
+    fail_verbose: bool,
+    /// The number of warnings printed by each command that printed some, by
+    /// label.
+    warnings: Vec<(String, usize)>,
+}
+impl BuildLog {
+    fn open(options: &BuildOptions) -> Result<Self> {
+        Ok(Self {
+            file: match options.log.as_deref() {
+                Some(path) => Some((
+                    path.to_string(),
+                    File::create(path)
+                        .map_err(|e| Error(format!("Failed to create file: {}: {}.", path, e)))?,
+                )),
+                None => None,
+            },
+            format: options.format,
+            timeout: options.timeout,
+            fail_verbose: options.fail_verbose,
+            warnings: vec![],
+        })
+    }
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        if let Some((path, file)) = &mut self.file {
+            file.write_all(bytes)
+                .map_err(|e| Error(format!("Failed to write file: {}: {}.", path, e)))?;
+        }
+        Ok(())
+    }
+    /// Prints a line of human readable output, which is only logged when
+    /// emitting JSON.
+    fn println(&mut self, line: impl ToString) -> Result<()> {
+        let line = line.to_string();
+        if self.format == OutputFormat::Text {
+            println!("{}", line);
+        }
+        self.write(format!("{}\n", line).as_bytes())
+    }
+    /// Prints `message` under `--explain`, as a `note` event in JSON.
+    fn note(&mut self, explain: bool, message: impl ToString) -> Result<()> {
+        if !explain {
+            return Ok(());
+        }
+        let message = message.to_string();
+        self.println(&message)?;
+        self.event("note", vec![("message", Json::str(&message))])
+    }
+    /// Emits a JSON event, which is ignored when emitting text.
+    fn event(&mut self, event: &str, mut fields: Vec<(&'static str, Json)>) -> Result<()> {
+        if self.format == OutputFormat::Json {
+            fields.insert(0, ("event", Json::str(event)));
+            let line = Json::Obj(fields).to_string();
+            println!("{}", line);
+            self.write(format!("{}\n", line).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Echoes and runs a command, forwarding its output to the terminal and the
+/// build log. If it fails, its output is labeled with `label`, typically the
+/// file being compiled. Returns whether the command succeeded.
+fn run_command(program: &str, args: &[String], label: &str, log: &mut BuildLog) -> Result<bool> {
+    let mut command = Command::new(program);
+    command.args(args);
+    let echo = echo(&command);
+    run(&mut command, echo, label, log)
+}
+
+/// `line` run by the platform's shell.
+fn shell(line: &str) -> Command {
+    let mut command = if cfg!(windows) {
+        Command::new("cmd")
+    } else {
+        Command::new("sh")
+    };
+    command
+        .arg(if cfg!(windows) { "/C" } else { "-c" })
+        .arg(line);
+    command
+}
+
+/// Runs a shell command line, as used by the `pre-build` and `post-build` hooks.
+fn run_hook(line: &str, project: &Project, log: &mut BuildLog) -> Result<bool> {
+    let mut command = shell(line);
+    command.envs(script_env(project));
+    run(&mut command, line.to_string(), line, log)
+}
+
+/// The command of a `$(command)` or `` `command` `` flag.
+fn substituted_command(flag: &str) -> Option<&str> {
+    flag.strip_prefix("$(")
+        .and_then(|flag| flag.strip_suffix(')'))
+        .or_else(|| {
+            flag.strip_prefix('`')
+                .and_then(|flag| flag.strip_suffix('`'))
+        })
+}
+
+/// The extension of the flag files `expand_flags` reads. Other `@file` words
+/// are the compiler's response files, passed on unchanged.
+const FLAG_FILE_EXTENSION: &str = "txt";
+
+/// Replaces each `$(command)` or `` `command` `` of key `key` by the words the
+/// command prints, failing if it does, and each `@file.txt` by the words of
+/// the file.
+fn expand_flags(flags: &[String], key: &str, timeout: Option<Duration>) -> Result<Vec<String>> {
+    let mut expanded = vec![];
+    for flag in flags {
+        if let Some(file) = flag.strip_prefix('@').filter(|file| {
+            Path::new(file).extension().and_then(|e| e.to_str()) == Some(FLAG_FILE_EXTENSION)
+        }) {
+            if !Path::new(file).is_file() {
+                return error!("Flag file not found: {} (key `{}`).", file, key);
+            }
+            let contents = fs::read_to_string(file).map_err(|e| {
                 Error(format!(
-                    "Failed to summon command: `{} {}`: {}",
-                    project.compiler,
-                    flags.join(" "),
+                    "Failed to read file: {} (key `{}`): {}.",
+                    file, key, e
+                ))
+            })?;
+            expanded.extend(contents.split_whitespace().map(|word| word.to_string()));
+            continue;
+        }
+        let line = match substituted_command(flag) {
+            Some(line) => line,
+            None => {
+                expanded.push(flag.clone());
+                continue;
+            }
+        };
+        let child = shell(line)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error(format!("Failed to summon command: `{}`: {}", line, e)))?;
+        let output = wait(child, timeout, line)?;
+        if !output.status.success() {
+            return error!(
+                "`{}` in key `{}` failed: {}",
+                line,
+                key,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        expanded.extend(
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .map(|word| word.to_string()),
+        );
+    }
+    Ok(expanded)
+}
+
+/// The number of diagnostics in `stderr` that are warnings, as printed by GCC
+/// and Clang.
+fn count_warnings(stderr: &[u8]) -> usize {
+    String::from_utf8_lossy(stderr)
+        .lines()
+        .filter(|line| line.starts_with("warning:") || line.contains(": warning:"))
+        .count()
+}
+
+fn run(command: &mut Command, echo: String, label: &str, log: &mut BuildLog) -> Result<bool> {
+    if !log.fail_verbose {
+        log.println(&echo)?;
+    }
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error(format!("Failed to summon command: `{}`: {}", echo, e)))?;
+    let output = wait(child, log.timeout, &echo)?;
+    let warnings = count_warnings(&output.stderr);
+    if warnings != 0 {
+        log.warnings.push((label.to_string(), warnings));
+    }
+    /* With `--fail-verbose`, only failed commands reach the terminal. */
+    let quiet = log.fail_verbose && output.status.success();
+    if quiet {
+        log.write(format!("{}\n", echo).as_bytes())?;
+    } else if log.fail_verbose {
+        log.println(&echo)?;
+    }
+    let has_output = !output.stdout.is_empty() || !output.stderr.is_empty();
+    let failed_with_output = !output.status.success() && has_output;
+    if failed_with_output {
+        log.write(format!("error in {}:\n", label).as_bytes())?;
+    }
+    if log.format == OutputFormat::Text && !quiet {
+        if failed_with_output {
+            eprintln!("\x1b[0;31merror in {}:\x1b[0m", label);
+        }
+        let _ = io::stdout().write_all(&output.stdout);
+        let _ = io::stderr().write_all(&output.stderr);
+    }
+    log.write(&output.stdout)?;
+    log.write(&output.stderr)?;
+    log.event(
+        "command",
+        vec![
+            ("command", Json::Str(echo)),
+            ("success", Json::Bool(output.status.success())),
+            (
+                "output",
+                Json::Str(format!(
+                    "{}{}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                )),
+            ),
+        ],
+    )?;
+    Ok(output.status.success())
+}
+
+/// The working directory left by `enter_manifest_dir`, restored when dropped
+/// so that callers keep their own.
+struct ManifestDir {
+    /// The previous working directory and the manifest directory, if it was
+    /// changed.
+    entered: Option<(PathBuf, PathBuf)>,
+}
+impl ManifestDir {
+    /// `path`, as given relative to the previous working directory, made
+    /// relative to the current one.
+    fn resolve(&self, path: &str) -> String {
+        match &self.entered {
+            Some((previous, dir)) => rebase(path, previous, dir),
+            None => path.to_string(),
+        }
+    }
+}
+impl Drop for ManifestDir {
+    fn drop(&mut self) {
+        if let Some((previous, _)) = &self.entered {
+            let _ = env::set_current_dir(previous);
+        }
+    }
+}
+
+/// `path`, relative to `from`, as seen from `to`: `./`-relative if it lies
+/// under `to`, absolute otherwise.
+fn rebase(path: &str, from: &Path, to: &Path) -> String {
+    let absolute = from.join(path);
+    match canonical(&absolute).strip_prefix(canonical(to)) {
+        Ok(relative) => Path::new(".").join(relative).to_string_lossy().to_string(),
+        Err(_) => absolute.to_string_lossy().to_string(),
+    }
+}
+
+/// Enters the directory of the `--manifest` file, if any, so that sources and
+/// build artifacts are found relative to it, until the returned `ManifestDir`
+/// is dropped. Returns the options with the manifest path made relative to the
+/// new working directory, and the paths given on the command line resolved
+/// against the previous one.
+fn enter_manifest_dir(options: &BuildOptions) -> Result<(BuildOptions, ManifestDir)> {
+    let mut options = options.clone();
+    let mut entered = None;
+    if let Some(manifest) = &options.manifest {
+        let path = Path::new(manifest);
+        if !path.is_file() {
+            return error!("Manifest not found: {}.", manifest);
+        }
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let previous = env::current_dir()
+                .map_err(|e| Error(format!("Failed to get current directory: {}.", e)))?;
+            let dir = previous.join(dir);
+            options.log = options
+                .log
+                .map(|log| previous.join(log).to_string_lossy().to_string());
+            options.output_dir = options
+                .output_dir
+                .map(|out| previous.join(out).to_string_lossy().to_string());
+            options.path = options.path.map(|path| rebase(&path, &previous, &dir));
+            env::set_current_dir(&dir).map_err(|e| {
+                Error(format!(
+                    "Failed to enter directory: {}: {}.",
+                    dir.display(),
                     e
                 ))
             })?;
-        if !status.success() {
-            return error!("Aborting at first failed command.");
+            entered = Some((previous, dir));
         }
-        if let BuildScript::Repeat = project.build_script {
-            run_build_script()?;
+        options.manifest = path
+            .file_name()
+            .map(|name| Path::new(".").join(name).to_string_lossy().to_string());
+    }
+    Ok((options, ManifestDir { entered }))
+}
+
+/// Loads the project as `build_project` would, without building it.
+pub fn read_project(options: &BuildOptions) -> Result<Project> {
+    let (options, _dir) = enter_manifest_dir(options)?;
+    load_project(&options)
+}
+
+/// Loads the manifest, `./ketchfile` by default, and applies the command line
+/// overrides to it.
+fn load_project(options: &BuildOptions) -> Result<Project> {
+    load_project_with_notes(options).map(|(project, _)| project)
+}
+
+/// `load_project`, with the notes on the overrides that `--explain` reports.
+fn load_project_with_notes(options: &BuildOptions) -> Result<(Project, Vec<String>)> {
+    let manifest = options.manifest.as_deref().unwrap_or("./ketchfile");
+    let mut project = Project::from_config(parse_file(manifest)?)?;
+    let mut notes = vec![];
+    if options.no_default_flags {
+        notes.push(format!(
+            "Dropping the configured flags: {}.",
+            project.flags.join(" ")
+        ));
+        project.flags.clear();
+    }
+    project.flags = expand_flags(&project.flags, "flags", options.timeout)?;
+    project.ldflags = expand_flags(&project.ldflags, "ldflags", options.timeout)?;
+    if options.both {
+        project.ptype = ProjectType::Both;
+    }
+    if let Some((dir, _)) = build_dir_override(options) {
+        project.build_dir = dir;
+    }
+    if options.library && !matches!(project.ptype, ProjectType::Static | ProjectType::Both) {
+        project.ptype = ProjectType::Static;
+    }
+    if let Some(compiler) = &options.compiler {
+        project.compiler = compiler.clone();
+    }
+    if let Some(standard) = options.standard {
+        project.standard = standard;
+    }
+    if let Some(linker) = &options.linker {
+        project.linker = Some(linker.clone());
+    }
+    if let Some(linker) = &project.linker {
+        if !KNOWN_LINKERS.contains(&linker.as_str()) {
+            strict_warning(
+                options,
+                format!(
+                    "`{}` is not a known linker. Known linkers: {}.",
+                    linker,
+                    KNOWN_LINKERS.join(", ")
+                ),
+            )?;
+        }
+        let family = version_output(&project.compiler, options.timeout)
+            .ok()
+            .map(|(_, output)| compiler_family(&output));
+        project.linker = checked_linker(options, &project.compiler, linker, family)?;
+    }
+    if options.release {
+        project.flags.extend(project.release_flags.clone());
+    } else if !options.no_debug {
+        project.flags.extend(project.debug_flags.clone());
+    }
+    if let Some(level) = &options.opt_level {
+        project.flags.retain(|flag| !flag.starts_with("-O"));
+        project.flags.push(level.clone());
+    }
+    if let Some(werror) = options.werror.or(project.werror) {
+        set_werror(&mut project.flags, werror);
+    }
+    if let Some(count) = options.max_errors {
+        match error_limit_flag(
+            detect_compiler_family(&project.compiler, options.timeout),
+            count,
+        ) {
+            Some(flag) => project.flags.push(flag),
+            None => strict_warning(
+                options,
+                format!(
+                    "`{}` is neither gcc nor clang, not limiting its errors.",
+                    project.compiler
+                ),
+            )?,
+        }
+    }
+    if options.check_flags || options.strict {
+        for (key, value) in [("flags", &project.flags), ("ldflags", &project.ldflags)] {
+            for flag in stray_flags(value) {
+                strict_warning(
+                    options,
+                    format!(
+                        "`{}` in key `{}` does not start with `-`. Is a dash missing?",
+                        flag, key
+                    ),
+                )?;
+            }
+        }
+    }
+    for dep in &project.dependencies {
+        if !Path::new(DEPS_DIR).join(dep.name()).is_dir() {
+            strict_warning(
+                options,
+                format!(
+                    "Dependency `{}` is declared but not installed in {}/{}.",
+                    dep,
+                    DEPS_DIR,
+                    dep.name()
+                ),
+            )?;
         }
     }
+    if let ProjectType::Static = project.ptype {
+        for (key, value) in &[("libs", &project.libs), ("ldflags", &project.ldflags)] {
+            if !value.is_empty() {
+                strict_warning(
+                    options,
+                    format!(
+                        "Key `{}` is ignored by static libraries, which are not linked.",
+                        key
+                    ),
+                )?;
+            }
+        }
+    }
+    Ok((project, notes))
+}
 
-    let program = if let ProjectType::Static = project.ptype {
-        "ar".to_string()
+/// Adds `-Werror` to `flags`, or without `werror` removes it and turns each
+/// `-Werror=<warning>` into `-W<warning>`, keeping the warning.
+fn set_werror(flags: &mut Vec<String>, werror: bool) {
+    flags.retain(|flag| flag != "-Werror");
+    if werror {
+        flags.push("-Werror".to_string());
     } else {
-        project.compiler
-    };
-    let mut args = objs.clone();
+        for flag in flags.iter_mut() {
+            if let Some(warning) = flag.strip_prefix("-Werror=") {
+                *flag = format!("-W{}", warning);
+            }
+        }
+    }
+}
 
-    match project.ptype {
-        ProjectType::Binary => args.extend(vec!["-o".to_string(), project.name]),
-        ProjectType::Static => {
-            args = vec!["rcs".to_string()];
-            args.extend(objs);
-            args.push(format!("lib{}.a", project.name));
-        }
-        ProjectType::Shared => args.extend(vec![
-            "-shared".to_string(),
-            "-o".to_string(),
-            format!("lib{}.so", project.name),
-        ]),
+/// The linkers gcc and clang select with `-fuse-ld`.
+const KNOWN_LINKERS: [&str; 4] = ["bfd", "gold", "lld", "mold"];
+
+/// `linker` if `compiler`, of `family`, takes `-fuse-ld`. A compiler that
+/// cannot be run (`family` is `None`) keeps it, the build reports the missing
+/// compiler instead.
+fn checked_linker(
+    options: &BuildOptions,
+    compiler: &str,
+    linker: &str,
+    family: Option<CompilerFamily>,
+) -> Result<Option<String>> {
+    match family {
+        None | Some(CompilerFamily::Gcc) | Some(CompilerFamily::Clang) => {
+            Ok(Some(linker.to_string()))
+        }
+        Some(_) => {
+            strict_warning(
+                options,
+                format!(
+                    "`{}` does not take `-fuse-ld`, ignoring linker `{}`.",
+                    compiler, linker
+                ),
+            )?;
+            Ok(None)
+        }
     }
+}
 
-    println!("{} {}", program, args.join(" "));
+/// The `--version` output of each compiler run so far, with whether it
+/// succeeded, so that a build asks each compiler once.
+static VERSION_OUTPUTS: Mutex<BTreeMap<String, (bool, String)>> = Mutex::new(BTreeMap::new());
 
-    let status = Command::new(&program).args(&args).status().map_err(|e| {
-        Error(format!(
-            "Failed to summon command: `{} {}`: {}",
-            program,
-            args.join(" "),
-            e
-        ))
-    })?;
-    if !status.success() {
-        return error!("Aborting at first failed command.");
+/// Whether `compiler --version` succeeded, and its output, stdout then
+/// stderr. Fails if it cannot be run or outlasts `timeout`. `cl` prints its
+/// banner even though it rejects `--version`.
+fn version_output(compiler: &str, timeout: Option<Duration>) -> Result<(bool, String)> {
+    let mut outputs = VERSION_OUTPUTS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(output) = outputs.get(compiler) {
+        return Ok(output.clone());
     }
+    let echo = format!("{} --version", compiler);
+    let child = Command::new(compiler)
+        .arg("--version")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error(format!("Failed to summon command: `{}`: {}", echo, e)))?;
+    let output = wait(child, timeout, &echo)?;
+    let output = (
+        output.status.success(),
+        format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ),
+    );
+    outputs.insert(compiler.to_string(), output.clone());
+    Ok(output)
+}
 
-    if let BuildScript::After = project.build_script {
-        run_build_script()
+/// The family of `compiler`, `Unknown` if it cannot be run in `timeout`.
+fn detect_compiler_family(compiler: &str, timeout: Option<Duration>) -> CompilerFamily {
+    version_output(compiler, timeout).map_or(CompilerFamily::Unknown, |(_, output)| {
+        compiler_family(&output)
+    })
+}
+
+/// The flag limiting the errors of a compiler of `family` to `count`.
+fn error_limit_flag(family: CompilerFamily, count: usize) -> Option<String> {
+    match family {
+        CompilerFamily::Gcc => Some(format!("-fmax-errors={}", count)),
+        CompilerFamily::Clang => Some(format!("-ferror-limit={}", count)),
+        CompilerFamily::Msvc | CompilerFamily::Unknown => None,
+    }
+}
+
+/// The compiler options whose argument is passed as a separate word, e.g.
+/// `-include config.h`.
+const FLAGS_WITH_ARGUMENT: [&str; 22] = [
+    "-include",
+    "-imacros",
+    "-isystem",
+    "-iquote",
+    "-idirafter",
+    "-iprefix",
+    "-I",
+    "-D",
+    "-U",
+    "-L",
+    "-l",
+    "-x",
+    "-o",
+    "-MF",
+    "-MT",
+    "-MQ",
+    "-Xlinker",
+    "-Xassembler",
+    "-Xpreprocessor",
+    "-framework",
+    "-arch",
+    "--param",
+];
+
+/// The words of `flags` that are neither options nor their arguments, which
+/// are usually options missing their dash. Response files (`@file`) are
+/// allowed.
+fn stray_flags(flags: &[String]) -> Vec<&String> {
+    let mut stray = vec![];
+    let mut argument = false;
+    for flag in flags {
+        if argument {
+            argument = false;
+        } else if FLAGS_WITH_ARGUMENT.contains(&flag.as_str()) {
+            argument = true;
+        } else if !flag.starts_with('-') && !flag.starts_with('@') {
+            stray.push(flag);
+        }
+    }
+    stray
+}
+
+/// Reports a likely mistake, as an error under `--strict`.
+fn strict_warning(options: &BuildOptions, message: String) -> Result<()> {
+    if options.strict {
+        error!("{}", message)
     } else {
+        warning!("{}", message);
         Ok(())
     }
 }
 
-fn read_dir(dir: &str) -> Result<Vec<String>> {
-    let readdir = fs::read_dir(dir)
-        .map_err(|e| Error(format!("Failed to read directory: {}: {}.", dir, e)))?;
-    let mut content = vec![];
+/// Warning flags of the default and common configurations that only apply to
+/// C, left out when compiling C++ to spare a warning per file.
+const C_ONLY_FLAGS: [&str; 8] = [
+    "-Werror=discarded-qualifiers",
+    "-Wdiscarded-qualifiers",
+    "-Wstrict-prototypes",
+    "-Wmissing-prototypes",
+    "-Wold-style-definition",
+    "-Wimplicit-function-declaration",
+    "-Wbad-function-cast",
+    "-Wnested-externs",
+];
 
-    for entry in readdir {
-        let entry =
-            entry.map_err(|e| Error(format!("Failed to get directory entry: {}: {}.", dir, e)))?;
-        let stringified = entry.path().to_string_lossy().to_string();
+/// The flags passed to every compilation of C sources, or of C++ ones with
+/// `cxx`, excluding the precompiled header.
+fn compile_flags(project: &Project, cxx: bool) -> Vec<String> {
+    let mut flags = project.flags.clone();
+    if cxx {
+        flags.retain(|flag| !C_ONLY_FLAGS.contains(&flag.as_str()));
+    }
+    if let ProjectType::Shared | ProjectType::Both = project.ptype {
+        flags.push("-fpic".to_string());
+    }
+    if cxx {
+        flags.push(format!("-std={}", project.cxx_standard));
+    } else {
+        flags.push(format!("-std={}", project.standard));
+    }
+    for dep in dependencies() {
+        let include = dep.join("include");
+        if include.is_dir() {
+            flags.push(format!("-I{}", include.display()));
+        }
+    }
+    flags
+}
 
-        if entry.path().is_dir() {
-            content.extend(read_dir(&stringified)?);
-        } else {
-            content.push(stringified);
+/// Declares the dependency `spec`, `[SOURCE:]REPO[@TAG]`, by appending a
+/// `dependency` entry to the manifest. Installing it is left to the user.
+pub fn add_dependency(options: &BuildOptions, spec: &str) -> Result<Dependency> {
+    let (options, _dir) = enter_manifest_dir(options)?;
+    let options = &options;
+    let dependency = spec.parse()?;
+    add_to_manifest(
+        options.manifest.as_deref().unwrap_or("./ketchfile"),
+        &dependency,
+    )?;
+    Ok(dependency)
+}
+
+fn add_to_manifest(manifest: &str, dependency: &Dependency) -> Result<()> {
+    let project = Project::from_config(parse_file(manifest)?)?;
+    if let Some(dep) = project
+        .dependencies
+        .iter()
+        .find(|dep| dep.name() == dependency.name())
+    {
+        return error!(
+            "Dependency `{}` is already declared as `{}`.",
+            dependency.name(),
+            dep
+        );
+    }
+    let mut contents = fs::read_to_string(manifest)
+        .map_err(|e| Error(format!("Failed to read file: {}: {}.", manifest, e)))?;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    let entry = dependency.to_config();
+    contents.push_str(&format!("{}\n", entry));
+    write_atomic(manifest, contents)?;
+    println!("Added `{}` to {}.", entry, manifest);
+    Ok(())
+}
+
+/// Removes the dependency `query`, given as `OWNER/REPO` or as `REPO`, from the
+/// manifest, along with its installation under `deps/`.
+pub fn remove_dependency(options: &BuildOptions, query: &str) -> Result<Dependency> {
+    let (options, _dir) = enter_manifest_dir(options)?;
+    let options = &options;
+    let dependency =
+        remove_from_manifest(options.manifest.as_deref().unwrap_or("./ketchfile"), query)?;
+    let installed = Path::new(DEPS_DIR).join(dependency.name());
+    if installed.is_dir() {
+        fs::remove_dir_all(&installed).map_err(|e| {
+            Error(format!(
+                "Failed to remove directory: {}: {}.",
+                installed.display(),
+                e
+            ))
+        })?;
+        println!("Removed {}.", installed.display());
+    }
+    Ok(dependency)
+}
+
+/// Deletes the line declaring `query`. Dependency names are unique, so a bare
+/// `REPO` is never ambiguous.
+fn remove_from_manifest(manifest: &str, query: &str) -> Result<Dependency> {
+    let project = Project::from_config(parse_file(manifest)?)?;
+    let dependency = match project
+        .dependencies
+        .into_iter()
+        .find(|dep| dep.repo == query || dep.name() == query)
+    {
+        Some(dependency) => dependency,
+        None => return error!("Dependency `{}` is not declared in {}.", query, manifest),
+    };
+    let contents = fs::read_to_string(manifest)
+        .map_err(|e| Error(format!("Failed to read file: {}: {}.", manifest, e)))?;
+    let entry = dependency.to_config();
+    let mut lines = contents.lines().collect::<Vec<&str>>();
+    match lines
+        .iter()
+        .position(|line| parse_string(line).is_ok_and(|vals| vals == [entry.clone()]))
+    {
+        Some(i) => lines.remove(i),
+        None => {
+            return error!(
+                "Failed to find `{}` on a line of its own in {}. Remove it by hand.",
+                entry, manifest
+            )
+        }
+    };
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    write_atomic(manifest, contents)?;
+    println!("Removed `{}` from {}.", entry, manifest);
+    Ok(dependency)
+}
+
+/// The directory holding installed dependencies, one per subdirectory.
+const DEPS_DIR: &str = "./deps";
+
+/// The installed dependencies of the current project, in a stable order.
+fn dependencies() -> Vec<PathBuf> {
+    dependencies_of(Path::new("."))
+}
+
+/// The installed dependencies of the project in `dir`, in a stable order.
+fn dependencies_of(dir: &Path) -> Vec<PathBuf> {
+    let mut deps = fs::read_dir(dir.join(DEPS_DIR))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect::<Vec<PathBuf>>()
+        })
+        .unwrap_or_default();
+    deps.sort();
+    deps
+}
+
+/// The static libraries of the dependencies of the project in `dir`, as seen
+/// from `dir`: those of the ketch projects among them, transitive ones
+/// included, each before the dependencies it uses so that the linker resolves
+/// its symbols, then the prebuilt `lib<dir>.a` of the others. The archive of a
+/// ketch project is named after the `name` of its ketchfile.
+fn dependency_archives(dir: &Path) -> Result<Vec<String>> {
+    let mut order = vec![];
+    dependency_build_order(dir, &mut vec![], &mut order)?;
+    let mut archives = vec![];
+    for dep in order.iter().rev() {
+        let manifest = dep.join("ketchfile").to_string_lossy().to_string();
+        let project = Project::from_config(parse_file(manifest)?)?;
+        archives.push(dep.join(static_lib_name(&project.name)));
+    }
+    for dep in dependencies_of(dir) {
+        if let (false, Some(name)) = (dep.join("ketchfile").is_file(), dep.file_name()) {
+            archives.push(dep.join(static_lib_name(&name.to_string_lossy())));
         }
     }
-    Ok(content)
+    Ok(archives
+        .into_iter()
+        .filter(|archive| archive.is_file())
+        .map(|archive| rebase(&archive.to_string_lossy(), dir, dir))
+        .collect())
+}
+
+/// The file name of the precompiled header, as found in the objects directory.
+fn pch_name(header: &str) -> Result<String> {
+    Path::new(header).file_name().map_or(
+        error!("Invalid precompiled header path: {}.", header),
+        |n| Ok(n.to_string_lossy().to_string()),
+    )
+}
+
+/// The extensions of the source files compiled by `build_project`. Assembly
+/// goes through the compiler driver too, `.S` being preprocessed first.
+const SOURCE_EXTENSIONS: [&str; 6] = ["c", "s", "S", "cpp", "cc", "cxx"];
+
+/// Whether `file` is compiled by the C++ compiler.
+fn is_cxx(file: &str) -> bool {
+    matches!(
+        Path::new(file).extension().and_then(|e| e.to_str()),
+        Some("cpp") | Some("cc") | Some("cxx")
+    )
+}
+
+/// The compiler of `file`: the C++ one for C++ sources, the C one otherwise.
+fn compiler_of<'a>(project: &'a Project, file: &str) -> &'a str {
+    if is_cxx(file) {
+        &project.cxx_compiler
+    } else {
+        &project.compiler
+    }
+}
+
+fn is_assembly(file: &str) -> bool {
+    matches!(
+        Path::new(file).extension().and_then(|e| e.to_str()),
+        Some("s") | Some("S")
+    )
+}
+
+/// The build directory chosen by `--output-dir` or, failing that, by the
+/// `KETCH_BUILD_DIR` environment variable, with where it comes from.
+/// Dependencies keep their own build directory, so that their objects don't
+/// mix with the project's.
+fn build_dir_override(options: &BuildOptions) -> Option<(String, &'static str)> {
+    choose_build_dir(options, env::var("KETCH_BUILD_DIR").ok())
+}
+
+/// The build directory override of `build_dir_override`, given the value of
+/// `KETCH_BUILD_DIR` as `env`.
+fn choose_build_dir(options: &BuildOptions, env: Option<String>) -> Option<(String, &'static str)> {
+    if options.library {
+        return None;
+    }
+    options
+        .output_dir
+        .clone()
+        .map(|dir| (dir, "command line"))
+        .or_else(|| {
+            env.filter(|dir| !dir.is_empty())
+                .map(|dir| (dir, "environment"))
+        })
+}
+
+/// The directory holding the project sources.
+const SOURCE_DIR: &str = "./src";
+
+/// `path` without its `.` components, so that `./src/a.c` and `src/a.c` compare
+/// equal.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+/// Whether `file` is `path` or lies under it.
+fn is_under(file: &str, path: &str) -> bool {
+    normalize(Path::new(file)).starts_with(normalize(Path::new(path)))
+}
+
+/// Fails unless `path`, as given to `ketch build`, is inside a source
+/// directory.
+fn check_build_path(project: &Project, path: &str) -> Result<()> {
+    let dirs = std::iter::once(SOURCE_DIR)
+        .chain(project.source_dirs.iter().map(|dir| dir.as_str()))
+        .collect::<Vec<&str>>();
+    if !Path::new(path).exists() {
+        return error!("Path not found: {}.", path);
+    }
+    if !dirs.iter().any(|dir| is_under(path, dir)) {
+        return error!(
+            "{} is not under a source directory ({}).",
+            path,
+            dirs.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// The directory the objects of a build go to, one per mode so that switching
+/// between debug and release builds recompiles nothing.
+fn objects_dir(build_dir: &str, options: &BuildOptions) -> String {
+    Path::new(build_dir)
+        .join(if options.release { "release" } else { "debug" })
+        .to_string_lossy()
+        .to_string()
+}
+
+/// The object built from `file` in `dir`, named after its path relative to the
+/// source directory, e.g. `./src/net/tcp.c` gives `<dir>/net_tcp.o` when `ext`
+/// is `o`. The root of an absolute path is dropped, so that the object stays
+/// in `dir`.
+fn object_path(dir: &str, file: &str, ext: &str) -> String {
+    let file = normalize(Path::new(file));
+    let source_dir = normalize(Path::new(SOURCE_DIR));
+    let relative = file.strip_prefix(&source_dir).unwrap_or(&file);
+    Path::new(dir)
+        .join(
+            relative
+                .with_extension(ext)
+                .components()
+                .filter(|c| !matches!(c, Component::RootDir | Component::Prefix(_)))
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("_"),
+        )
+        .to_string_lossy()
+        .to_string()
+}
+
+/// The file name of an executable on the target platform.
+fn binary_name(name: &str) -> String {
+    if cfg!(windows) {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// The name of the files adding flags to the sources of their directory.
+const DIRECTORY_FLAGS: &str = ".ketchflags";
+
+/// The flags of the `.ketchflags` files in the directories containing `file`,
+/// outermost first so that deeper ones come last. The search stops below the
+/// project root, or below the root of the repository or project holding an
+/// absolute `file`.
+fn directory_flags(file: &str) -> Result<Vec<String>> {
+    let mut files = vec![];
+    let mut dir = Path::new(file).parent();
+    while let Some(current) = dir.filter(|d| !normalize(d).as_os_str().is_empty()) {
+        if current.join("ketchfile").is_file() || current.join(".git").exists() {
+            break;
+        }
+        let path = current.join(DIRECTORY_FLAGS);
+        if path.is_file() {
+            files.push(path);
+        }
+        dir = current.parent();
+    }
+    let mut flags = vec![];
+    for path in files.iter().rev() {
+        flags.extend(get_all(&parse_file(path.to_string_lossy())?, "flags")?);
+    }
+    Ok(flags)
+}
+
+/// The flags compiling `file` into its object, writing the headers it depends
+/// on to a `.d` file next to the object.
+fn file_flags(dir: &str, file: &str, ext: &str) -> Vec<String> {
+    vec![
+        "-MMD".to_string(),
+        "-c".to_string(),
+        file.to_string(),
+        "-o".to_string(),
+        object_path(dir, file, ext),
+    ]
+}
+
+/// Why a source file has to be compiled again.
+#[derive(Clone, Debug, PartialEq)]
+enum RebuildReason {
+    MissingObject,
+    CommandChanged,
+    SourceChanged,
+    HeaderChanged(String),
+}
+impl fmt::Display for RebuildReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingObject => write!(f, "the object file is missing"),
+            Self::CommandChanged => write!(f, "the compile command changed"),
+            Self::SourceChanged => write!(f, "the source file is newer than the object file"),
+            Self::HeaderChanged(header) => write!(f, "`{}` changed", header),
+        }
+    }
+}
+
+/// Hashes of the command lines that produced each object, stored in
+/// `build/.fingerprints` so that changing flags invalidates the objects.
+struct Fingerprints {
+    path: String,
+    entries: BTreeMap<String, u64>,
+}
+impl Fingerprints {
+    fn load(path: &str) -> Self {
+        let entries = fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let (hash, obj) = line.split_once(' ')?;
+                Some((obj.to_string(), u64::from_str_radix(hash, 16).ok()?))
+            })
+            .collect();
+        Self {
+            path: path.to_string(),
+            entries,
+        }
+    }
+    fn matches(&self, obj: &str, command: &[String]) -> bool {
+        self.entries.get(obj) == Some(&fingerprint(command))
+    }
+    fn update(&mut self, obj: &str, command: &[String]) {
+        self.entries.insert(obj.to_string(), fingerprint(command));
+    }
+    fn save(&self) -> Result<()> {
+        write_atomic(
+            &self.path,
+            self.entries
+                .iter()
+                .map(|(obj, hash)| format!("{:016x} {}\n", hash, obj))
+                .collect::<String>(),
+        )
+    }
+}
+
+/// A 64-bit FNV-1a hash of a command line, stable across ketch versions.
+fn fingerprint(command: &[String]) -> u64 {
+    command
+        .join("\0")
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// Checks `file` against its object, the command that last produced it and
+/// the headers recorded in its `.d` file.
+fn rebuild_reason(
+    file: &str,
+    obj: &str,
+    command: &[String],
+    fingerprints: &Fingerprints,
+) -> Option<RebuildReason> {
+    if is_outdated(obj, &[]) {
+        return Some(RebuildReason::MissingObject);
+    }
+    if !fingerprints.matches(obj, command) {
+        return Some(RebuildReason::CommandChanged);
+    }
+    if is_outdated(obj, &[file]) {
+        return Some(RebuildReason::SourceChanged);
+    }
+    let depfile = fs::read_to_string(Path::new(obj).with_extension("d")).unwrap_or_default();
+    /* Skip the `target:` rule head and the source file itself. */
+    depfile
+        .split_whitespace()
+        .filter(|dep| *dep != "\\" && !dep.ends_with(':'))
+        .skip(1)
+        .find(|header| is_outdated(obj, &[header]))
+        .map(|header| RebuildReason::HeaderChanged(header.to_string()))
+}
+
+/// The file name of a static library on the target platform.
+fn static_lib_name(name: &str) -> String {
+    if cfg!(windows) {
+        format!("{}.lib", name)
+    } else {
+        format!("lib{}.a", name)
+    }
+}
+
+/// The file name of a shared library on the target platform.
+fn shared_lib_name(name: &str) -> String {
+    if cfg!(windows) {
+        format!("{}.dll", name)
+    } else if cfg!(target_os = "macos") {
+        format!("lib{}.dylib", name)
+    } else {
+        format!("lib{}.so", name)
+    }
+}
+
+/// The commands producing the project artifacts from its objects and the
+/// dependency `archives`, linking with the C++ compiler if `cxx`, for its
+/// runtime.
+fn link_commands(
+    project: &Project,
+    objs: &[String],
+    archives: &[String],
+    cxx: bool,
+) -> Vec<(String, Vec<String>)> {
+    let archive = || {
+        let mut args = vec!["rcs".to_string(), static_lib_name(&project.name)];
+        args.extend(objs.iter().cloned());
+        let mut commands = vec![(project.archiver.clone(), args)];
+        if let Some(ranlib) = &project.ranlib {
+            commands.push((ranlib.clone(), vec![static_lib_name(&project.name)]));
+        }
+        commands
+    };
+    let link = |output: Vec<String>| {
+        let mut args = objs.to_vec();
+        args.extend(output);
+        args.extend(archives.iter().cloned());
+        if let Some(linker) = &project.linker {
+            args.push(format!("-fuse-ld={}", linker));
+        }
+        args.extend(project.ldflags.iter().cloned());
+        args.extend(project.libs.iter().map(|lib| format!("-l{}", lib)));
+        let driver = if cxx {
+            &project.cxx_compiler
+        } else {
+            &project.compiler
+        };
+        (driver.clone(), args)
+    };
+    let shared = || {
+        link(vec![
+            if cfg!(target_os = "macos") {
+                "-dynamiclib"
+            } else {
+                "-shared"
+            }
+            .to_string(),
+            "-o".to_string(),
+            shared_lib_name(&project.name),
+        ])
+    };
+
+    match project.ptype {
+        ProjectType::Binary => vec![link(vec!["-o".to_string(), binary_name(&project.name)])],
+        ProjectType::Static => archive(),
+        ProjectType::Shared => vec![shared()],
+        ProjectType::Both => {
+            let mut commands = archive();
+            commands.push(shared());
+            commands
+        }
+    }
+}
+
+/// Fails if `compiler --version` reports a version older than `required`.
+fn check_compiler_version(
+    compiler: &str,
+    required: &[u32],
+    options: &BuildOptions,
+    log: &mut BuildLog,
+) -> Result<()> {
+    let output = match version_output(compiler, options.timeout)? {
+        (true, output) => output,
+        (false, _) => return error!("`{} --version` failed.", compiler),
+    };
+    let join = |version: &[u32]| {
+        version
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<String>>()
+            .join(".")
+    };
+    let found = match compiler_version(&output) {
+        Some(found) => found,
+        None => return error!("Failed to detect the version of `{}`.", compiler),
+    };
+    log.note(
+        options.explain,
+        format!("{}: version {}.", compiler, join(&found)),
+    )?;
+    if is_older(&found, required) {
+        return error!(
+            "`{}` is version {}, but key `cc-version-min` requires {}.",
+            compiler,
+            join(&found),
+            join(required)
+        );
+    }
+    Ok(())
+}
+
+/// `bytes` in a human readable unit, e.g. `248 KiB`.
+fn human_size(bytes: u64) -> String {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+    if bytes < KIB {
+        format!("{} B", bytes)
+    } else if bytes < MIB {
+        format!("{} KiB", bytes / KIB)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / MIB as f64)
+    }
+}
+
+/// The files produced by `link_commands`.
+fn artifacts(project: &Project) -> Vec<String> {
+    match project.ptype {
+        ProjectType::Binary => vec![binary_name(&project.name)],
+        ProjectType::Static => vec![static_lib_name(&project.name)],
+        ProjectType::Shared => vec![shared_lib_name(&project.name)],
+        ProjectType::Both => vec![
+            static_lib_name(&project.name),
+            shared_lib_name(&project.name),
+        ],
+    }
+}
+
+/// A pkg-config file describing a library built under `prefix`, with its
+/// headers in `prefix/include`.
+fn pkg_config(project: &Project, prefix: &Path) -> String {
+    let mut pc = format!(
+        "prefix={}\nincludedir=${{prefix}}/include\nlibdir=${{prefix}}\n\nName: {}\nDescription: {}\nVersion: {}\n",
+        prefix.display(),
+        project.name,
+        project.description.as_deref().unwrap_or(&project.name),
+        project.version
+    );
+    if let Some(homepage) = &project.homepage {
+        pc.push_str(&format!("URL: {}\n", homepage));
+    }
+    pc.push_str("Cflags: -I${includedir}\n");
+    pc.push_str(&format!("Libs: -L${{libdir}} -l{}\n", project.name));
+    let mut private = project.ldflags.clone();
+    private.extend(project.libs.iter().map(|lib| format!("-l{}", lib)));
+    if !private.is_empty() {
+        pc.push_str(&format!("Libs.private: {}\n", private.join(" ")));
+    }
+    pc
+}
+
+/// The compiler invocation that `build_project` would use, either for `file`
+/// or, without a file, only the flags shared by all sources.
+pub fn compile_command(options: &BuildOptions, file: Option<&str>) -> Result<String> {
+    let (options, manifest_dir) = enter_manifest_dir(options)?;
+    let options = &options;
+    let file = file.map(|file| manifest_dir.resolve(file));
+    let file = file.as_deref();
+    let project = load_project(options)?;
+    let cxx = file.is_some_and(is_cxx);
+    let mut flags = compile_flags(&project, cxx);
+    if let (Some(header), false) = (
+        &project.pch,
+        file.is_some_and(|file| is_assembly(file) || cxx),
+    ) {
+        flags.extend(vec![
+            "-include".to_string(),
+            Path::new(&objects_dir(&project.build_dir, options))
+                .join(pch_name(header)?)
+                .to_string_lossy()
+                .to_string(),
+        ]);
+    }
+    if let Some(file) = file {
+        let file = if file.starts_with("./") || Path::new(file).is_absolute() {
+            file.to_string()
+        } else {
+            format!("./{}", file)
+        };
+        flags.extend(directory_flags(&file)?);
+        flags.extend(file_flags(
+            &objects_dir(&project.build_dir, options),
+            &file,
+            &project.obj_ext,
+        ));
+    }
+    Ok(format!(
+        "{} {}",
+        compiler_of(&project, file.unwrap_or_default()),
+        flags.join(" ")
+    ))
+}
+
+/// A warning when the project is not named after `dir`, which usually means a
+/// ketchfile was copied from another project.
+fn name_mismatch(project: &Project, dir: &Path) -> Option<String> {
+    let dir_name = dir.file_name()?.to_string_lossy();
+    if dir_name == project.name {
+        None
+    } else {
+        Some(format!(
+            "The project is named `{}` but its directory is `{}`. Was the ketchfile copied?",
+            project.name, dir_name
+        ))
+    }
+}
+
+/// Each setting of `project`, as read from `vals`, with its effective value
+/// under `options` and where that value comes from: `default`, `ketchfile`,
+/// `environment` or `command line`.
+fn config_sources(
+    vals: &[ConfigValue],
+    project: &Project,
+    options: &BuildOptions,
+) -> Vec<(&'static str, String, &'static str)> {
+    let source = |key: &str, cli: bool| {
+        if cli {
+            "command line"
+        } else if find_val(vals, key).is_some() {
+            "ketchfile"
+        } else {
+            "default"
+        }
+    };
+    let unless = |value: String, used: bool| {
+        if used {
+            value
+        } else {
+            format!("{} (unused)", value)
+        }
+    };
+    let (build_dir, build_dir_source) =
+        build_dir_override(options).unwrap_or((project.build_dir.clone(), "default"));
+    /* The project's flags end with its extra flags. */
+    let extra_flags = get_list(vals, "extra-flags")
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let flags = &project.flags[..project.flags.len().saturating_sub(extra_flags.len())];
+    let mut rows = vec![
+        ("name", project.name.clone(), "ketchfile"),
+        ("version", project.version.clone(), "ketchfile"),
+        (
+            "type",
+            if options.both {
+                ProjectType::Both.to_string()
+            } else {
+                project.ptype.to_string()
+            },
+            source("type", options.both),
+        ),
+        (
+            "cc",
+            options.compiler.clone().unwrap_or(project.compiler.clone()),
+            source("cc", options.compiler.is_some()),
+        ),
+        (
+            "standard",
+            options.standard.unwrap_or(project.standard).to_string(),
+            source("standard", options.standard.is_some()),
+        ),
+        ("cxx", project.cxx_compiler.clone(), source("cxx", false)),
+        (
+            "cxxstd",
+            project.cxx_standard.clone(),
+            source("cxxstd", false),
+        ),
+        (
+            "flags",
+            if options.no_default_flags {
+                String::new()
+            } else {
+                flags.join(" ")
+            },
+            source("flags", options.no_default_flags),
+        ),
+        (
+            "extra-flags",
+            unless(extra_flags.join(" "), !options.no_default_flags),
+            source("extra-flags", false),
+        ),
+        (
+            "debug-flags",
+            unless(
+                project.debug_flags.join(" "),
+                !options.release && !options.no_debug,
+            ),
+            source("debug-flags", false),
+        ),
+        (
+            "release-flags",
+            unless(project.release_flags.join(" "), options.release),
+            source("release-flags", false),
+        ),
+    ];
+    if let Some(level) = &options.opt_level {
+        rows.push(("opt-level", level.clone(), "command line"));
+    }
+    rows.extend([
+        (
+            "werror",
+            options
+                .werror
+                .or(project.werror)
+                .map_or("unset".to_string(), |werror| werror.to_string()),
+            source("werror", options.werror.is_some()),
+        ),
+        ("libs", project.libs.join(" "), source("libs", false)),
+        (
+            "ldflags",
+            project.ldflags.join(" "),
+            source("ldflags", false),
+        ),
+        (
+            "linker",
+            project.linker.clone().unwrap_or_default(),
+            source("linker", options.linker.is_some()),
+        ),
+        ("ar", project.archiver.clone(), source("ar", false)),
+        (
+            "ranlib",
+            project.ranlib.clone().unwrap_or_default(),
+            source("ranlib", false),
+        ),
+        ("obj-ext", project.obj_ext.clone(), source("obj-ext", false)),
+        (
+            "source-dirs",
+            project.source_dirs.join(" "),
+            source("source-dirs", false),
+        ),
+        (
+            "pch",
+            project.pch.clone().unwrap_or_default(),
+            source("pch", false),
+        ),
+        (
+            "unity",
+            (options.unity || project.unity).to_string(),
+            source("unity", options.unity),
+        ),
+        ("build-dir", build_dir.clone(), build_dir_source),
+        (
+            "objects",
+            objects_dir(&build_dir, options),
+            if options.release {
+                "command line"
+            } else {
+                build_dir_source
+            },
+        ),
+    ]);
+    rows
+}
+
+/// Every setting with its effective value and where it comes from, followed by
+/// the resulting compiler flags.
+pub fn explain_config(options: &BuildOptions) -> Result<Vec<(&'static str, String, &'static str)>> {
+    let (options, _dir) = enter_manifest_dir(options)?;
+    let options = &options;
+    let manifest = options.manifest.as_deref().unwrap_or("./ketchfile");
+    let vals = parse_file(manifest)?;
+    let project = Project::from_config(vals.clone())?;
+    let mut rows = config_sources(
+        &resolve_platforms(vals, env::consts::OS)?,
+        &project,
+        options,
+    );
+    let loaded = load_project(options)?;
+    rows.push((
+        "compiler flags",
+        compile_flags(&loaded, false).join(" "),
+        "all of the above",
+    ));
+    rows.push((
+        "c++ compiler flags",
+        compile_flags(&loaded, true).join(" "),
+        "all of the above",
+    ));
+    Ok(rows)
+}
+
+/// The dependency graph of the project in `dir`, named `name`: the names of
+/// the project and of its installed dependencies, direct or not, and an edge
+/// from each to its direct dependencies. A dependency installed in several
+/// places is a single node, and cycles are kept as edges.
+fn dependency_graph(name: &str, dir: &Path) -> (Vec<String>, Vec<(String, String)>) {
+    let mut nodes = vec![name.to_string()];
+    let mut edges = vec![];
+    let mut visited = vec![];
+    let mut pending = vec![(name.to_string(), dir.to_path_buf())];
+    while let Some((from, dir)) = pending.pop() {
+        if visited.contains(&canonical(&dir)) {
+            continue;
+        }
+        visited.push(canonical(&dir));
+        for dep in dependencies_of(&dir) {
+            let to = match dep.file_name() {
+                Some(to) => to.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if !nodes.contains(&to) {
+                nodes.push(to.clone());
+            }
+            if !edges.contains(&(from.clone(), to.clone())) {
+                edges.push((from.clone(), to.clone()));
+            }
+            pending.push((to, dep));
+        }
+    }
+    (nodes, edges)
+}
+
+/// The dependency graph of the project as Graphviz DOT or, with the JSON
+/// format, as an object of `nodes` and `edges`.
+pub fn project_graph(options: &BuildOptions) -> Result<String> {
+    let (options, _dir) = enter_manifest_dir(options)?;
+    let manifest = options.manifest.as_deref().unwrap_or("./ketchfile");
+    let project = Project::from_config(parse_file(manifest)?)?;
+    let (nodes, edges) = dependency_graph(&project.name, Path::new("."));
+    Ok(match options.format {
+        OutputFormat::Json => Json::Obj(vec![
+            ("nodes", Json::Arr(nodes.iter().map(Json::str).collect())),
+            (
+                "edges",
+                Json::Arr(
+                    edges
+                        .iter()
+                        .map(|(from, to)| {
+                            Json::Obj(vec![("from", Json::str(from)), ("to", Json::str(to))])
+                        })
+                        .collect(),
+                ),
+            ),
+        ])
+        .to_string(),
+        OutputFormat::Text => {
+            /* DOT and JSON quote strings the same way. */
+            let mut dot = format!("digraph {} {{\n", Json::str(&project.name));
+            for node in &nodes {
+                dot.push_str(&format!("    {};\n", Json::str(node)));
+            }
+            for (from, to) in &edges {
+                dot.push_str(&format!("    {} -> {};\n", Json::str(from), Json::str(to)));
+            }
+            dot.push('}');
+            dot
+        }
+    })
+}
+
+/// Builds the project in the current directory, or the one of `--manifest`,
+/// after its dependencies. With the JSON format, a failure is also reported as
+/// an `error` event.
+pub fn build_project(options: &BuildOptions) -> Result<BuildReport> {
+    let result =
+        enter_manifest_dir(options).and_then(|(options, _dir)| build_with_dependencies(&options));
+    if let (Err(e), OutputFormat::Json) = (&result, options.format) {
+        println!(
+            "{}",
+            Json::Obj(vec![
+                ("event", Json::str("error")),
+                ("message", Json::str(&e.0))
+            ])
+        );
+    }
+    result
+}
+
+/// `dir` with its symbolic links resolved, so that a project reached through a
+/// `deps/` link compares equal to itself.
+fn canonical(dir: &Path) -> PathBuf {
+    dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf())
+}
+
+/// The dependencies under `deps/` of the project in `dir` that are ketch
+/// projects, recursively and depth first so that each one comes after its own
+/// dependencies. `visiting` holds the projects being walked, to detect cycles.
+fn dependency_build_order(
+    dir: &Path,
+    visiting: &mut Vec<PathBuf>,
+    order: &mut Vec<PathBuf>,
+) -> Result<()> {
+    visiting.push(canonical(dir));
+    for dep in dependencies_of(dir) {
+        if !dep.join("ketchfile").is_file() {
+            continue;
+        }
+        let dep = canonical(&dep);
+        if visiting.contains(&dep) {
+            return error!("Dependency cycle detected at {}.", dep.display());
+        }
+        if order.contains(&dep) {
+            continue;
+        }
+        dependency_build_order(&dep, visiting, order)?;
+        order.push(dep);
+    }
+    visiting.pop();
+    Ok(())
+}
+
+/// Builds the dependencies of the project in the current directory, in the
+/// order of `dependency_build_order`, then the project itself.
+fn build_with_dependencies(options: &BuildOptions) -> Result<BuildReport> {
+    let current = env::current_dir()
+        .map_err(|e| Error(format!("Failed to get current directory: {}.", e)))?;
+    let mut order = vec![];
+    dependency_build_order(&current, &mut vec![], &mut order)?;
+    let dep_options = BuildOptions {
+        log: None,
+        timings: false,
+        both: false,
+        standard: None,
+        manifest: None,
+        path: None,
+        output_dir: None,
+        unity: false,
+        library: true,
+        ..options.clone()
+    };
+    let mut cxx = false;
+    for dep in order {
+        env::set_current_dir(&dep).map_err(|e| {
+            Error(format!(
+                "Failed to enter directory: {}: {}.",
+                dep.display(),
+                e
+            ))
+        })?;
+        let result = build(&dep_options, false);
+        env::set_current_dir(&current).map_err(|e| {
+            Error(format!(
+                "Failed to enter directory: {}: {}.",
+                current.display(),
+                e
+            ))
+        })?;
+        cxx |= result?.cxx;
+    }
+    build(options, cxx)
+}
+
+/// Runs the steps before compilation, `false` if the build script is all of it.
+fn prepare_sources(project: &Project, log: &mut BuildLog) -> Result<bool> {
+    match project.build_script {
+        BuildScript::Only => {
+            run_build_script(project, log)?;
+            return Ok(false);
+        }
+        BuildScript::Before => run_build_script(project, log)?,
+        _ => {}
+    }
+    for hook in &project.pre_build {
+        if !run_hook(hook, project, log)? {
+            return error!("Pre-build hook failed: `{}`.", hook);
+        }
+    }
+    Ok(true)
+}
+
+/// Builds the project in the current directory, linking with the C++ compiler
+/// if it has C++ sources or `deps_cxx`, when a dependency has.
+fn build(options: &BuildOptions, deps_cxx: bool) -> Result<BuildReport> {
+    let start = Instant::now();
+    let (project, notes) = load_project_with_notes(options)?;
+    let mut log = BuildLog::open(options)?;
+    for note in notes {
+        log.note(options.explain, note)?;
+    }
+    let cwd = env::current_dir()
+        .map_err(|e| Error(format!("Failed to get current directory: {}.", e)))?;
+    if let Some(message) = name_mismatch(&project, &cwd) {
+        /* A dependency's directory is not up to the user, `--strict` spares it. */
+        if options.library {
+            warning!("{}", message);
+        } else {
+            strict_warning(options, message)?;
+        }
+    }
+    if let ProjectType::Static | ProjectType::Both = project.ptype {
+        for (key, program) in [
+            ("ar", Some(&project.archiver)),
+            ("ranlib", project.ranlib.as_ref()),
+        ] {
+            if let Some(program) = program {
+                if find_program(program).is_none() {
+                    return error!(
+                        "Program `{}` (key `{}`) was not found in PATH.",
+                        program, key
+                    );
+                }
+            }
+        }
+    }
+
+    if let Some(required) = &project.cc_version_min {
+        check_compiler_version(&project.compiler, required, options, &mut log)?;
+    }
+
+    let mut report = BuildReport::default();
+    if !prepare_sources(&project, &mut log)? {
+        report.duration = start.elapsed();
+        return Ok(report);
+    }
+    let dir = objects_dir(&project.build_dir, options);
+    fs::create_dir_all(&dir)
+        .map_err(|e| Error(format!("Failed to create directory: {}: {}.", dir, e)))?;
+    let in_dir = |file: &str| Path::new(&dir).join(file).to_string_lossy().to_string();
+    let mut files = project_sources(&project)?;
+    if let Some(path) = &options.path {
+        check_build_path(&project, path)?;
+    }
+    if options.unity || project.unity {
+        if options.path.is_some() {
+            return error!("A unity build compiles every source, a path cannot be given.");
+        }
+        files = unity_sources(&dir, files, &cwd)?;
+    }
+    let mut objs = vec![];
+    let mut failed = vec![];
+    let mut timings = vec![];
+
+    log.println(format!(
+        "\x1b[0;32m*\x1b[0m Compiling {}::{} ({} files)...",
+        project.name,
+        project.version,
+        files.len()
+    ))?;
+    let common_flags = compile_flags(&project, false);
+    let cxx_flags = compile_flags(&project, true);
+    let cxx = deps_cxx || files.iter().any(|file| is_cxx(file));
+    report.cxx = cxx;
+    let mut pch_flags = vec![];
+    let mut fingerprints = Fingerprints::load(&in_dir(".fingerprints"));
+
+    let mut pch_rebuilt = None;
+    if let Some(header) = &project.pch {
+        let name = pch_name(header)?;
+        let gch = in_dir(&format!("{}.gch", name));
+        let mut flags = common_flags.clone();
+        flags.extend(vec![
+            "-x".to_string(),
+            "c-header".to_string(),
+            "-c".to_string(),
+            header.to_string(),
+            "-o".to_string(),
+            gch.clone(),
+        ]);
+        let mut command = vec![project.compiler.clone()];
+        command.extend(flags.iter().cloned());
+        let mut precompiled = true;
+        if is_outdated(&gch, &[header]) || !fingerprints.matches(&gch, &command) {
+            if run_command(&project.compiler, &flags, header, &mut log)? {
+                fingerprints.update(&gch, &command);
+                pch_rebuilt = Some(RebuildReason::HeaderChanged(header.to_string()));
+            } else {
+                strict_warning(
+                    options,
+                    format!(
+                        "Failed to precompile {}, including it directly instead.",
+                        header
+                    ),
+                )?;
+                let _ = fs::remove_file(&gch);
+                precompiled = false;
+            }
+        }
+        /* The compiler picks up `<name>.gch` next to the included path. */
+        pch_flags = vec![
+            "-include".to_string(),
+            if precompiled {
+                in_dir(&name)
+            } else {
+                header.to_string()
+            },
+        ];
+    }
+
+    for file in files {
+        let built = object_path(&dir, &file, &project.obj_ext);
+        objs.push(built.clone());
+        if let Some(path) = options
+            .path
+            .as_deref()
+            .filter(|path| !is_under(&file, path))
+        {
+            if !Path::new(&built).is_file() {
+                return error!(
+                    "{} is outside of {} and has never been built. Build the whole project first.",
+                    file, path
+                );
+            }
+            if options.explain {
+                log.println(format!("{}: outside of {}, skipped.", file, path))?;
+            }
+            report.skipped.push(file);
+            continue;
+        }
+        let compiler = compiler_of(&project, &file);
+        let mut flags = if is_cxx(&file) {
+            cxx_flags.clone()
+        } else {
+            common_flags.clone()
+        };
+        flags.extend(directory_flags(&file)?);
+        if !is_assembly(&file) && !is_cxx(&file) {
+            flags.extend(pch_flags.iter().cloned());
+        }
+        flags.extend(file_flags(&dir, &file, &project.obj_ext));
+        let mut command = vec![compiler.to_string()];
+        command.extend(flags.iter().cloned());
+        let reason = rebuild_reason(&file, &built, &command, &fingerprints);
+        match reason.or_else(|| pch_rebuilt.clone()) {
+            None => {
+                if options.explain {
+                    log.println(format!("{}: up to date, skipped.", file))?;
+                }
+                log.event("skip", vec![("file", Json::str(&file))])?;
+                report.skipped.push(file.clone());
+                timings.push((file, None));
+                continue;
+            }
+            Some(reason) if options.explain => {
+                log.println(format!("{}: compiling because {}.", file, reason))?
+            }
+            Some(_) => {}
+        }
+        log.event("compile", vec![("file", Json::str(&file))])?;
+        let compile_start = Instant::now();
+        let success = run_command(compiler, &flags, &file, &mut log)?;
+        timings.push((file.clone(), Some(compile_start.elapsed())));
+        if !success {
+            log.event(
+                "error",
+                vec![
+                    ("file", Json::str(&file)),
+                    ("message", Json::str("Compilation failed.")),
+                ],
+            )?;
+            if options.keep_going {
+                failed.push(file);
+                if options.max_errors.is_some_and(|max| failed.len() >= max) {
+                    log.println(format!("Stopping after {} failed file(s).", failed.len()))?;
+                    break;
+                }
+                continue;
+            }
+            fingerprints.save()?;
+            return error!("Aborting at first failed command.");
+        }
+        fingerprints.update(&built, &command);
+        report.compiled.push(file);
+        if let BuildScript::Repeat = project.build_script {
+            run_build_script(&project, &mut log)?;
+        }
+    }
+    fingerprints.save()?;
+    log.println(format!(
+        "Compiled {} file(s), skipped {}, failed {}.",
+        report.compiled.len(),
+        report.skipped.len(),
+        failed.len()
+    ))?;
+    log.event(
+        "done",
+        vec![
+            ("compiled", Json::Num(report.compiled.len() as u64)),
+            ("skipped", Json::Num(report.skipped.len() as u64)),
+            ("failed", Json::Num(failed.len() as u64)),
+        ],
+    )?;
+    if !failed.is_empty() {
+        return error!(
+            "Failed to compile {} file(s): {}.",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+
+    let objs_count = objs.len();
+    if options.emit == Emit::Obj {
+        for obj in &objs {
+            log.println(obj)?;
+        }
+        report.artifacts = objs;
+    } else {
+        let archives = dependency_archives(&cwd)?;
+        for (program, args) in link_commands(&project, &objs, &archives, cxx) {
+            if !run_command(&program, &args, &program, &mut log)? {
+                return error!("Aborting at first failed command.");
+            }
+        }
+        report.artifacts = artifacts(&project);
+        if !matches!(project.ptype, ProjectType::Binary) {
+            let prefix = env::current_dir()
+                .map_err(|e| Error(format!("Failed to get current directory: {}.", e)))?;
+            let pc = in_dir(&format!("lib{}.pc", project.name));
+            write_atomic(&pc, pkg_config(&project, &prefix))?;
+        }
+    }
+    for artifact in &report.artifacts {
+        let size = fs::metadata(artifact).map(|m| m.len()).unwrap_or_default();
+        if options.emit == Emit::Bin {
+            if *artifact == static_lib_name(&project.name) {
+                log.println(format!(
+                    "Built {} ({}, {} object(s))",
+                    artifact,
+                    human_size(size),
+                    objs_count
+                ))?;
+            } else {
+                log.println(format!("Built {} ({})", artifact, human_size(size)))?;
+            }
+        }
+        log.event(
+            "artifact",
+            vec![("path", Json::str(artifact)), ("size", Json::Num(size))],
+        )?;
+    }
+
+    for hook in &project.post_build {
+        if !run_hook(hook, &project, &mut log)? {
+            return error!("Post-build hook failed: `{}`.", hook);
+        }
+    }
+    if let BuildScript::After = project.build_script {
+        run_build_script(&project, &mut log)?;
+    }
+    report.duration = start.elapsed();
+    let warnings = log.warnings.iter().map(|(_, count)| count).sum::<usize>();
+    if warnings != 0 {
+        log.println(format!("Built with {} warning(s).", warnings))?;
+        if options.explain {
+            let mut worst = log.warnings.clone();
+            worst.sort_by_key(|(_, count)| Reverse(*count));
+            for (label, count) in worst {
+                log.println(format!("  {:>5}  {}", count, label))?;
+            }
+        }
+        log.event("warnings", vec![("count", Json::Num(warnings as u64))])?;
+    }
+    if options.timings {
+        timings.sort_by_key(|(_, duration)| Reverse(*duration));
+        log.println("Timings:")?;
+        for (file, duration) in timings {
+            match duration {
+                Some(duration) => {
+                    log.println(format!("  {:>8.3}s  {}", duration.as_secs_f64(), file))?
+                }
+                None => log.println(format!("  {:>8.3}s  {} (skipped)", 0.0, file))?,
+            }
+        }
+        log.println(format!("  {:>8.3}s  total", report.duration.as_secs_f64()))?;
+    }
+    Ok(report)
+}
+
+/// Compiles every source with `-fsyntax-only`, reporting errors without
+/// writing objects or linking.
+pub fn check_project(options: &BuildOptions) -> Result<()> {
+    let (options, _dir) = enter_manifest_dir(options)?;
+    let options = &options;
+    let (project, notes) = load_project_with_notes(options)?;
+    let mut log = BuildLog::open(options)?;
+    for note in notes {
+        log.note(options.explain, note)?;
+    }
+    if !prepare_sources(&project, &mut log)? {
+        return Ok(());
+    }
+    let files = project_sources(&project)?;
+    log.println(format!(
+        "\x1b[0;32m*\x1b[0m Checking {}::{} ({} files)...",
+        project.name,
+        project.version,
+        files.len()
+    ))?;
+    let common_flags = compile_flags(&project, false);
+    let cxx_flags = compile_flags(&project, true);
+    let mut failed = vec![];
+    for file in &files {
+        let mut flags = if is_cxx(file) {
+            cxx_flags.clone()
+        } else {
+            common_flags.clone()
+        };
+        flags.extend(directory_flags(file)?);
+        flags.extend(vec!["-fsyntax-only".to_string(), file.to_string()]);
+        log.event("check", vec![("file", Json::str(file))])?;
+        if !run_command(compiler_of(&project, file), &flags, file, &mut log)? {
+            log.event(
+                "error",
+                vec![
+                    ("file", Json::str(file)),
+                    ("message", Json::str("Check failed.")),
+                ],
+            )?;
+            if !options.keep_going {
+                return error!("Aborting at first failed command.");
+            }
+            failed.push(file.clone());
+        }
+    }
+    log.println(format!(
+        "Checked {} file(s), failed {}.",
+        files.len(),
+        failed.len()
+    ))?;
+    log.event(
+        "done",
+        vec![
+            ("checked", Json::Num(files.len() as u64)),
+            ("failed", Json::Num(failed.len() as u64)),
+        ],
+    )?;
+    if !failed.is_empty() {
+        return error!(
+            "Failed to check {} file(s): {}.",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// The programs `doctor_project` looks for, as `(role, program, critical)`.
+/// `git` is only needed to fetch dependencies, so its absence is not fatal.
+fn required_tools(project: &Project, dir: &Path) -> Vec<(&'static str, String, bool)> {
+    let library = matches!(project.ptype, ProjectType::Static | ProjectType::Both);
+    let mut tools = vec![
+        ("compiler", project.compiler.clone(), true),
+        ("archiver", project.archiver.clone(), library),
+    ];
+    if project_sources(project).is_ok_and(|files| files.iter().any(|file| is_cxx(file))) {
+        tools.push(("C++ compiler", project.cxx_compiler.clone(), true));
+    }
+    if let Some(ranlib) = &project.ranlib {
+        tools.push(("ranlib", ranlib.clone(), library));
+    }
+    tools.push(("git", "git".to_string(), false));
+    for (script, interpreter) in POSSIBLE_SCRIPTS {
+        if dir.join(script).exists() {
+            let critical = !matches!(project.build_script, BuildScript::None);
+            tools.push((
+                "build script interpreter",
+                interpreter.to_string(),
+                critical,
+            ));
+        }
+    }
+    tools
+}
+
+/// Reports whether the ketchfile parses, the sources exist and the programs a
+/// build needs are installed. Fails if anything but an optional tool is
+/// missing.
+pub fn doctor_project(options: &BuildOptions) -> Result<()> {
+    let (options, _dir) = enter_manifest_dir(options)?;
+    let options = &options;
+    let mut failed = 0;
+    let mut report = |ok: bool, critical: bool, what: String| {
+        let status = match (ok, critical) {
+            (true, _) => "\x1b[0;32mok\x1b[0m     ",
+            (false, true) => "\x1b[0;31merror\x1b[0m  ",
+            (false, false) => "\x1b[0;33mwarning\x1b[0m",
+        };
+        println!("{} {}", status, what);
+        if !ok && critical {
+            failed += 1;
+        }
+    };
+    let project = match load_project(options) {
+        Ok(project) => {
+            report(true, true, "ketchfile".to_string());
+            project
+        }
+        Err(e) => {
+            report(false, true, format!("ketchfile: {}", e.0));
+            let mut project = Project::builder().name("").version("").build()?;
+            if let Some(compiler) = &options.compiler {
+                project.compiler = compiler.clone();
+            }
+            project
+        }
+    };
+    match Path::new(SOURCE_DIR).is_dir() {
+        true => report(true, true, format!("source directory {}", SOURCE_DIR)),
+        false => report(
+            false,
+            true,
+            format!("source directory {} does not exist", SOURCE_DIR),
+        ),
+    }
+    for (role, program, critical) in required_tools(&project, Path::new(".")) {
+        match find_program(&program) {
+            Some(path) => report(
+                true,
+                critical,
+                format!("{} `{}`: {}", role, program, path.display()),
+            ),
+            None => report(
+                false,
+                critical,
+                format!("{} `{}` was not found in PATH", role, program),
+            ),
+        }
+    }
+    if failed != 0 {
+        return error!("{} check(s) failed.", failed);
+    }
+    Ok(())
+}
+
+/// Lists the sources under `dir` in a stable order, so that objects are
+/// compiled and archived deterministically.
+fn source_files(dir: &str) -> Result<Vec<String>> {
+    let mut files = read_dir(dir)?
+        .into_iter()
+        .filter(|f| {
+            Path::new(f)
+                .extension()
+                .is_some_and(|e| SOURCE_EXTENSIONS.iter().any(|s| e == *s))
+        })
+        .collect::<Vec<String>>();
+    files.sort();
+    Ok(files)
+}
+
+/// The sources of `project`: those under `./src`, then those under each of its
+/// `source-dirs`. Fails if two of them would share an object file.
+fn project_sources(project: &Project) -> Result<Vec<String>> {
+    let mut files = source_files(SOURCE_DIR)?;
+    for dir in &project.source_dirs {
+        if !Path::new(dir).is_dir() {
+            return error!("Source directory not found: {}.", dir);
+        }
+        files.extend(source_files(dir)?);
+    }
+    let mut objects = BTreeMap::new();
+    for file in &files {
+        let object = object_path(&project.build_dir, file, &project.obj_ext);
+        if let Some(other) = objects.insert(object.clone(), file) {
+            return error!(
+                "`{}` and `{}` would both be compiled to `{}`.",
+                other, file, object
+            );
+        }
+    }
+    Ok(files)
+}
+
+/// The contents of the unity source of a project, including each of `sources`
+/// by its absolute path under `root`.
+fn unity_file(sources: &[String], root: &Path) -> String {
+    let mut contents = "/* Generated by ketch, do not edit. */\n".to_string();
+    for source in sources {
+        let path = root.join(normalize(Path::new(source)));
+        let path = path
+            .to_string_lossy()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        contents.push_str(&format!("#include \"{}\"\n", path));
+    }
+    contents
+}
+
+/// Replaces the C sources among `files` with `unity.c` in the objects
+/// directory `dir`, which includes them all, keeping the assembly and C++
+/// ones. The unity source is only rewritten when the sources change, since
+/// the objects are rebuilt when it is newer.
+fn unity_sources(dir: &str, files: Vec<String>, root: &Path) -> Result<Vec<String>> {
+    let (sources, others): (Vec<String>, Vec<String>) = files
+        .into_iter()
+        .partition(|file| !is_assembly(file) && !is_cxx(file));
+    if sources.is_empty() {
+        return Ok(others);
+    }
+    let unity = Path::new(dir).join("unity.c").to_string_lossy().to_string();
+    let contents = unity_file(&sources, root);
+    if fs::read_to_string(&unity).ok().as_deref() != Some(contents.as_str()) {
+        write_atomic(&unity, contents)?;
+    }
+    Ok(std::iter::once(unity).chain(others).collect())
+}
+
+/// Whether `target` is missing or older than any of `sources`.
+fn is_outdated(target: &str, sources: &[&str]) -> bool {
+    let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+    match modified(target) {
+        None => true,
+        Some(built) => sources
+            .iter()
+            .any(|source| modified(source).is_none_or(|m| m > built)),
+    }
+}
+
+fn read_dir(dir: &str) -> Result<Vec<String>> {
+    let readdir = fs::read_dir(dir)
+        .map_err(|e| Error(format!("Failed to read directory: {}: {}.", dir, e)))?;
+    let mut content = vec![];
+
+    for entry in readdir {
+        let entry =
+            entry.map_err(|e| Error(format!("Failed to get directory entry: {}: {}.", dir, e)))?;
+        /* Skip hidden files, editor lock files and VCS metadata. */
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let stringified = entry.path().to_string_lossy().to_string();
+
+        if entry.path().is_dir() {
+            content.extend(read_dir(&stringified)?);
+        } else {
+            content.push(stringified);
+        }
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("ketch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn deterministic_sources() -> Result<()> {
+        let dir = temp_dir("sources");
+        for file in &[
+            "b.c", "a/z.c", "a/b.c", "c.h", "0.c", "d.S", "e.s", "f.cc", ".#b.c", ".git/g.c",
+        ] {
+            let path = Path::new(&dir).join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        let first = source_files(&dir)?;
+        assert_eq!(first, source_files(&dir)?);
+        assert_eq!(
+            first,
+            ["0.c", "a/b.c", "a/z.c", "b.c", "d.S", "e.s", "f.cc"]
+                .iter()
+                .map(|f| Path::new(&dir).join(f).to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+        );
+        fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn flags_change_triggers_rebuild() {
+        let dir = temp_dir("fingerprints");
+        let file = format!("{}/main.c", dir);
+        let obj = format!("{}/main.o", dir);
+        File::create(&file).unwrap();
+        File::create(&obj).unwrap();
+        let debug = vec!["cc".to_string(), "-c".to_string(), file.clone()];
+        let mut release = debug.clone();
+        release.push("-O3".to_string());
+
+        let mut fingerprints = Fingerprints::load(&format!("{}/.fingerprints", dir));
+        assert_eq!(
+            rebuild_reason(&file, &obj, &debug, &fingerprints),
+            Some(RebuildReason::CommandChanged)
+        );
+        fingerprints.update(&obj, &debug);
+        fingerprints.save().unwrap();
+
+        let fingerprints = Fingerprints::load(&format!("{}/.fingerprints", dir));
+        assert_eq!(rebuild_reason(&file, &obj, &debug, &fingerprints), None);
+        assert_eq!(
+            rebuild_reason(&file, &obj, &release, &fingerprints),
+            Some(RebuildReason::CommandChanged)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn library_names() {
+        assert_eq!(static_lib_name("foo"), "libfoo.a");
+        assert_eq!(shared_lib_name("foo"), "libfoo.so");
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn library_names() {
+        assert_eq!(static_lib_name("foo"), "libfoo.a");
+        assert_eq!(shared_lib_name("foo"), "libfoo.dylib");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn library_names() {
+        assert_eq!(static_lib_name("foo"), "foo.lib");
+        assert_eq!(shared_lib_name("foo"), "foo.dll");
+    }
+
+    #[test]
+    fn object_names() {
+        let dir = objects_dir("./build", &BuildOptions::default());
+        let build = |name: &str| Path::new(".").join("build").join("debug").join(name);
+        assert_eq!(
+            object_path(&dir, "./src/main.c", "o"),
+            build("main.o").to_string_lossy()
+        );
+        assert_eq!(
+            object_path(&dir, "src/main.c", "o"),
+            build("main.o").to_string_lossy()
+        );
+        assert_eq!(
+            object_path(&dir, "./src/net/tcp/conn.c", "o"),
+            build("net_tcp_conn.o").to_string_lossy()
+        );
+        assert_eq!(
+            object_path(&dir, "./src/ré.s", "o"),
+            build("ré.o").to_string_lossy()
+        );
+        assert_eq!(
+            object_path(&dir, "lib/x.c", "o"),
+            build("lib_x.o").to_string_lossy()
+        );
+        assert_eq!(
+            object_path(&dir, "./src/main.c", "obj"),
+            build("main.obj").to_string_lossy()
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            object_path("/tmp/kc/debug", "/tmp/kc/unity.c", "o"),
+            "/tmp/kc/debug/tmp_kc_unity.o"
+        );
+    }
+
+    #[test]
+    fn pkg_config_file() -> Result<()> {
+        let project = Project::builder()
+            .name("foo")
+            .version("1.2")
+            .ptype(ProjectType::Static)
+            .libs(vec!["m".to_string()])
+            .build()?;
+        assert_eq!(
+            pkg_config(&project, Path::new("/opt/foo")),
+            "prefix=/opt/foo
+includedir=${prefix}/include
+libdir=${prefix}
+
+Name: foo
+Description: foo
+Version: 1.2
+Cflags: -I${includedir}
+Libs: -L${libdir} -lfoo
+Libs.private: -lm
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn build_script_echo() -> Result<()> {
+        let dir = temp_dir("script");
+        fs::write(Path::new(&dir).join("build.sh"), "exit 0\n").unwrap();
+        let project = Project::builder().name("x").version("1").build()?;
+        let command = build_script_command(Path::new(&dir), &project)?;
+        assert_eq!(command.get_program(), "sh");
+        assert_eq!(
+            echo(&command),
+            format!("sh {}", Path::new(&dir).join("build.sh").display())
+        );
+        assert!(build_script_command(&Path::new(&dir).join("none"), &project).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn source_dirs() -> Result<()> {
+        let dir = temp_dir("source-dirs");
+        let base = |name: &str| format!("{}/{}", dir, name);
+        let project = Project::builder()
+            .name("x")
+            .version("1")
+            .source_dirs(vec![base("lib")])
+            .build()?;
+        assert!(project_sources(&project).is_err());
+        fs::create_dir_all(base("lib")).unwrap();
+        fs::write(base("lib/a.c"), "").unwrap();
+        fs::write(base("lib/b.h"), "").unwrap();
+        assert_eq!(project_sources(&project)?, vec![base("lib/a.c")]);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn timeout() {
+        let spawn = |script: &str| {
+            Command::new("sh")
+                .args(["-c", script])
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap()
+        };
+        let timeout = Some(Duration::from_millis(200));
+        let output = wait(spawn("echo ok"), timeout, "echo ok").unwrap();
+        assert_eq!(output.stdout, b"ok\n");
+        assert!(wait(spawn("sleep 5"), timeout, "sleep 5").is_err());
+    }
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(human_size(12), "12 B");
+        assert_eq!(human_size(248 * 1024 + 100), "248 KiB");
+        assert_eq!(human_size(3 * 1024 * 1024 / 2), "1.5 MiB");
+    }
+
+    #[test]
+    fn directory_flag_files() -> Result<()> {
+        let dir = temp_dir("ketchflags");
+        fs::create_dir_all(format!("{}/src/net", dir)).unwrap();
+        fs::write(
+            format!("{}/src/{}", dir, DIRECTORY_FLAGS),
+            "(flags -DA -DB)",
+        )
+        .unwrap();
+        fs::write(
+            format!("{}/src/net/{}", dir, DIRECTORY_FLAGS),
+            "(flags -DC)",
+        )
+        .unwrap();
+        assert_eq!(
+            directory_flags(&format!("{}/src/net/tcp.c", dir))?,
+            vec!["-DA", "-DB", "-DC"]
+        );
+        assert_eq!(
+            directory_flags(&format!("{}/src/a.c", dir))?,
+            vec!["-DA", "-DB"]
+        );
+        assert!(directory_flags("./src/a.c")?.is_empty());
+        fs::create_dir_all(format!("{}/app/src", dir)).unwrap();
+        fs::write(format!("{}/app/ketchfile", dir), "(name app)\n(version 1)").unwrap();
+        fs::write(
+            format!("{}/app/src/{}", dir, DIRECTORY_FLAGS),
+            "(flags -DD)",
+        )
+        .unwrap();
+        fs::write(format!("{}/{}", dir, DIRECTORY_FLAGS), "(flags -DOUT)").unwrap();
+        assert_eq!(
+            directory_flags(&format!("{}/app/src/main.c", dir))?,
+            vec!["-DD"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn stray() {
+        let flags = [
+            "-Wall", "O3", "-include", "config.h", "@opts", "-x", "c", "g",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
+        assert_eq!(stray_flags(&flags), vec!["O3", "g"]);
+    }
+
+    #[test]
+    fn main_detection() {
+        assert!(defines_main("int\nmain (void)\n{}"));
+        assert!(defines_main("int main(int argc, char **argv) {}"));
+        assert!(!defines_main("int domain(void);"));
+        assert!(!defines_main("int main_loop(void);"));
+        assert!(!defines_main("/* maintainer */"));
+    }
+
+    #[test]
+    fn doctor_tools() -> Result<()> {
+        let dir = temp_dir("doctor");
+        File::create(Path::new(&dir).join("build.sh")).unwrap();
+        let project = Project::builder()
+            .name("x")
+            .version("1")
+            .ptype(ProjectType::Static)
+            .build_script(BuildScript::Before)
+            .build()?;
+        let tools = required_tools(&project, Path::new(&dir));
+        assert_eq!(
+            tools,
+            vec![
+                ("compiler", "cc".to_string(), true),
+                ("archiver", "ar".to_string(), true),
+                ("git", "git".to_string(), false),
+                ("build script interpreter", "sh".to_string(), true),
+            ]
+        );
+        let binary = Project::builder().name("x").version("1").build()?;
+        assert!(!required_tools(&binary, Path::new(&dir))[1].2);
+        Ok(())
+    }
+
+    #[test]
+    fn add() -> Result<()> {
+        let dir = temp_dir("add");
+        let manifest = Path::new(&dir).join("ketchfile");
+        let manifest = manifest.to_string_lossy();
+        fs::write(manifest.as_ref(), "(name x)\n(version 1)").unwrap();
+        add_to_manifest(&manifest, &"github:owner/repo@v1".parse()?)?;
+        assert_eq!(
+            fs::read_to_string(manifest.as_ref()).unwrap(),
+            "(name x)\n(version 1)\n(dependency github owner/repo v1)\n"
+        );
+        let e = add_to_manifest(&manifest, &"other/repo".parse()?)
+            .err()
+            .unwrap();
+        assert!(e.0.starts_with("Dependency `repo` is already declared"));
+        Ok(())
+    }
+
+    #[test]
+    fn remove() -> Result<()> {
+        let dir = temp_dir("remove");
+        let manifest = Path::new(&dir).join("ketchfile");
+        let manifest = manifest.to_string_lossy();
+        fs::write(
+            manifest.as_ref(),
+            "(name x)\n(dependency github a/b)\n(version 1)\n(dependency github c/d v2)\n",
+        )
+        .unwrap();
+        assert_eq!(remove_from_manifest(&manifest, "d")?.repo, "c/d");
+        assert_eq!(remove_from_manifest(&manifest, "a/b")?.repo, "a/b");
+        assert_eq!(
+            fs::read_to_string(manifest.as_ref()).unwrap(),
+            "(name x)\n(version 1)\n"
+        );
+        let e = remove_from_manifest(&manifest, "b").err().unwrap();
+        assert!(e.0.starts_with("Dependency `b` is not declared"));
+        Ok(())
+    }
+
+    #[test]
+    fn build_path_scope() -> Result<()> {
+        assert!(is_under("./src/net/tcp.c", "src/net/"));
+        assert!(is_under("./src/net/tcp.c", "./src/net/tcp.c"));
+        assert!(!is_under("./src/network.c", "src/net"));
+        assert!(!is_under("./lib/a.c", "src"));
+        let project = Project::builder().name("x").version("1").build()?;
+        let e = check_build_path(&project, "Cargo.toml").err().unwrap();
+        assert_eq!(e.0, "Cargo.toml is not under a source directory (./src).");
+        assert!(check_build_path(&project, "src/nonexistent").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn directory_name() -> Result<()> {
+        let project = Project::builder().name("net").version("1").build()?;
+        assert_eq!(name_mismatch(&project, Path::new("/src/net")), None);
+        assert_eq!(name_mismatch(&project, Path::new("/")), None);
+        assert!(name_mismatch(&project, Path::new("/src/http"))
+            .unwrap()
+            .contains("`net` but its directory is `http`"));
+        Ok(())
+    }
+
+    #[test]
+    fn command_flags() -> Result<()> {
+        let flags = ["-O2", "$(echo -Ia   -DB)", "`echo -lm`", "$x"].map(|s| s.to_string());
+        assert_eq!(
+            expand_flags(&flags, "flags", None)?,
+            vec!["-O2", "-Ia", "-DB", "-lm", "$x"]
+        );
+        let e = expand_flags(&["$(echo oops >&2; false)".to_string()], "ldflags", None)
+            .err()
+            .unwrap();
+        assert_eq!(e.0, "`echo oops >&2; false` in key `ldflags` failed: oops");
+        Ok(())
+    }
+
+    #[test]
+    fn flag_files() -> Result<()> {
+        let dir = temp_dir("flag-files");
+        let file = Path::new(&dir).join("flags.txt");
+        fs::write(&file, "-DA=1\n  -Iinclude\t-g\n").unwrap();
+        let flags = [
+            "-Wall".to_string(),
+            format!("@{}", file.display()),
+            "-O2".to_string(),
+        ];
+        assert_eq!(
+            expand_flags(&flags, "flags", None)?,
+            vec!["-Wall", "-DA=1", "-Iinclude", "-g", "-O2"]
+        );
+        let rsp = Path::new(&dir).join("args.rsp");
+        fs::write(&rsp, "-DB=2").unwrap();
+        let response = [
+            format!("@{}", rsp.display()),
+            "@missing.rsp".to_string(),
+            "@opts".to_string(),
+        ];
+        assert_eq!(expand_flags(&response, "flags", None)?, response);
+        let missing = format!("{}/missing.txt", dir);
+        let e = expand_flags(&[format!("@{}", missing)], "ldflags", None)
+            .err()
+            .unwrap();
+        assert_eq!(
+            e.0,
+            format!("Flag file not found: {} (key `ldflags`).", missing)
+        );
+        fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn debug_flags() -> Result<()> {
+        let dir = temp_dir("debug");
+        let manifest = Path::new(&dir).join("ketchfile");
+        fs::write(&manifest, "(name x)\n(version 1)\n(flags -Wall)").unwrap();
+        let flags = |options: BuildOptions| -> Result<Vec<String>> {
+            let options = BuildOptions {
+                manifest: Some(manifest.to_string_lossy().to_string()),
+                ..options
+            };
+            Ok(load_project(&options)?.flags)
+        };
+        assert_eq!(flags(BuildOptions::default())?, vec!["-Wall", "-g", "-O0"]);
+        let release = BuildOptions {
+            release: true,
+            ..Default::default()
+        };
+        assert_eq!(flags(release)?, vec!["-Wall", "-O3", "-DNDEBUG"]);
+        let no_debug = BuildOptions {
+            no_debug: true,
+            ..Default::default()
+        };
+        assert_eq!(flags(no_debug)?, vec!["-Wall"]);
+        fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn werror() {
+        let flags = || -> Vec<String> {
+            vec!["-Wall", "-Werror", "-Werror=discarded-qualifiers"]
+                .into_iter()
+                .map(String::from)
+                .collect()
+        };
+        let mut on = flags();
+        set_werror(&mut on, true);
+        assert_eq!(on, vec!["-Wall", "-Werror=discarded-qualifiers", "-Werror"]);
+        let mut off = flags();
+        set_werror(&mut off, false);
+        assert_eq!(off, vec!["-Wall", "-Wdiscarded-qualifiers"]);
+    }
+
+    #[test]
+    fn warnings() {
+        let stderr = b"./src/a.c: In function 'f':\n\
+./src/a.c:3:7: warning: unused variable 'x' [-Wunused-variable]\n\
+    3 |   int x;\n\
+./src/a.c:4:1: error: expected ';'\n\
+warning: argument unused during compilation: '-pthread'\n";
+        assert_eq!(count_warnings(stderr), 2);
+        assert_eq!(count_warnings(b""), 0);
+    }
+
+    #[test]
+    fn sources() -> Result<()> {
+        let vals = parse_string(
+            "(name x)\n(version 1)\n(cc clang)\n(flags -Wall)\n(extra-flags -DX)\n(release-flags -O2)",
+        )?;
+        let project = Project::from_config(vals.clone())?;
+        let options = BuildOptions {
+            release: true,
+            standard: Some(crate::project::parse_standard("c11")?),
+            ..Default::default()
+        };
+        let rows = config_sources(&vals, &project, &options);
+        let row = |key: &str| {
+            rows.iter()
+                .find(|(k, _, _)| *k == key)
+                .map(|(_, value, source)| (value.as_str(), *source))
+                .unwrap()
+        };
+        assert_eq!(row("cc"), ("clang", "ketchfile"));
+        assert_eq!(row("standard"), ("c11", "command line"));
+        assert_eq!(row("flags"), ("-Wall", "ketchfile"));
+        assert_eq!(row("extra-flags"), ("-DX", "ketchfile"));
+        assert_eq!(row("ar"), ("ar", "default"));
+        assert_eq!(row("debug-flags"), ("-g -O0 (unused)", "default"));
+        assert_eq!(row("release-flags"), ("-O2", "ketchfile"));
+        Ok(())
+    }
+
+    #[test]
+    fn graph() {
+        let dir = temp_dir("graph");
+        for path in [
+            "deps/a/deps/c",
+            "deps/b/deps/c/deps/d/deps",
+            "deps/b/deps/a",
+        ] {
+            fs::create_dir_all(Path::new(&dir).join(path)).unwrap();
+        }
+        let mut expected = vec![
+            ("x", "a"),
+            ("x", "b"),
+            ("a", "c"),
+            ("b", "a"),
+            ("b", "c"),
+            ("c", "d"),
+        ];
+        /* d depends on b, which depends on d through c. */
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(
+                Path::new(&dir).join("deps/b"),
+                Path::new(&dir).join("deps/b/deps/c/deps/d/deps/b"),
+            )
+            .unwrap();
+            expected.push(("d", "b"));
+        }
+        let (mut nodes, edges) = dependency_graph("x", Path::new(&dir));
+        nodes.sort();
+        assert_eq!(nodes, vec!["a", "b", "c", "d", "x"]);
+        let mut edges = edges;
+        edges.sort();
+        expected.sort();
+        assert_eq!(
+            edges,
+            expected
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn atomic_write() -> Result<()> {
+        let dir = temp_dir("atomic");
+        let path = Path::new(&dir).join("ketchfile");
+        write_atomic(&path, "(name x)\n")?;
+        let e = write_atomic_with(&path, |file| {
+            file.write_all(b"(na")?;
+            Err(io::Error::other("interrupted"))
+        })
+        .err()
+        .unwrap();
+        assert!(e.0.ends_with(": interrupted."));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "(name x)\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        write_atomic(&path, "(name y)\n")?;
+        assert_eq!(fs::read_to_string(&path).unwrap(), "(name y)\n");
+        fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn build_dir_env() {
+        let options = BuildOptions::default();
+        let env = || Some("/tmp/ketch-cache".to_string());
+        assert_eq!(
+            choose_build_dir(&options, env()),
+            Some(("/tmp/ketch-cache".to_string(), "environment"))
+        );
+        assert_eq!(
+            object_path(
+                &objects_dir("/tmp/ketch-cache", &options),
+                "./src/main.c",
+                "o"
+            ),
+            "/tmp/ketch-cache/debug/main.o"
+        );
+        let cli = BuildOptions {
+            output_dir: Some("out".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            choose_build_dir(&cli, env()),
+            Some(("out".to_string(), "command line"))
+        );
+        assert_eq!(choose_build_dir(&options, Some(String::new())), None);
+        assert_eq!(choose_build_dir(&options, None), None);
+        let dependency = BuildOptions {
+            library: true,
+            ..cli
+        };
+        assert_eq!(choose_build_dir(&dependency, env()), None);
+    }
+
+    #[test]
+    fn ignored_files() -> Result<()> {
+        let project = Project::builder()
+            .name("foo")
+            .version("1")
+            .ptype(ProjectType::Both)
+            .build()?;
+        assert_eq!(
+            gitignore(&project),
+            format!(
+                "build/\ndeps/\n/{}\n/{}\n",
+                static_lib_name("foo"),
+                shared_lib_name("foo")
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn linker() -> Result<()> {
+        let objs = vec!["build/debug/main.o".to_string()];
+        let mut project = Project::builder()
+            .name("foo")
+            .version("1")
+            .linker("lld")
+            .build()?;
+        let commands = link_commands(&project, &objs, &[], false);
+        assert!(commands[0].1.contains(&"-fuse-ld=lld".to_string()));
+        project.ptype = ProjectType::Static;
+        for (_, args) in link_commands(&project, &objs, &[], false) {
+            assert!(!args.iter().any(|arg| arg.starts_with("-fuse-ld")));
+        }
+        let options = BuildOptions::default();
+        let lld = Some("lld".to_string());
+        for family in [CompilerFamily::Gcc, CompilerFamily::Clang] {
+            assert_eq!(checked_linker(&options, "cc", "lld", Some(family))?, lld);
+        }
+        assert_eq!(
+            checked_linker(&options, "./no-such-compiler", "lld", None)?,
+            lld
+        );
+        assert_eq!(
+            checked_linker(&options, "tcc", "lld", Some(CompilerFamily::Unknown))?,
+            None
+        );
+        assert_eq!(
+            checked_linker(&options, "cl", "lld", Some(CompilerFamily::Msvc))?,
+            None
+        );
+        let strict = BuildOptions {
+            strict: true,
+            ..BuildOptions::default()
+        };
+        assert!(checked_linker(&strict, "tcc", "lld", Some(CompilerFamily::Unknown)).is_err());
+        assert_eq!(checked_linker(&strict, "cc", "lld", None)?, lld);
+        Ok(())
+    }
+
+    #[test]
+    fn unity() {
+        let sources = vec!["./src/main.c".to_string(), "lib/x.c".to_string()];
+        assert_eq!(
+            unity_file(&sources, Path::new("/p")),
+            "/* Generated by ketch, do not edit. */\n#include \"/p/src/main.c\"\n#include \"/p/lib/x.c\"\n"
+        );
+    }
+
+    #[test]
+    fn error_limit() {
+        assert_eq!(
+            error_limit_flag(CompilerFamily::Gcc, 5),
+            Some("-fmax-errors=5".to_string())
+        );
+        assert_eq!(
+            error_limit_flag(CompilerFamily::Clang, 5),
+            Some("-ferror-limit=5".to_string())
+        );
+        assert_eq!(error_limit_flag(CompilerFamily::Unknown, 5), None);
+        assert_eq!(
+            detect_compiler_family("./no-such-compiler", None),
+            CompilerFamily::Unknown
+        );
+    }
+
+    #[test]
+    fn cxx_sources() -> Result<()> {
+        let project = Project::builder().name("foo").version("1").build()?;
+        assert_eq!(compiler_of(&project, "./src/main.c"), "cc");
+        assert_eq!(compiler_of(&project, "./src/app.cpp"), "c++");
+        let flags = compile_flags(&project, true);
+        assert!(flags.contains(&"-std=c++17".to_string()));
+        assert!(!flags.contains(&"-Werror=discarded-qualifiers".to_string()));
+        let objs = vec!["build/debug/app.o".to_string()];
+        assert_eq!(link_commands(&project, &objs, &[], true)[0].0, "c++");
+        assert_eq!(link_commands(&project, &objs, &[], false)[0].0, "cc");
+        Ok(())
+    }
+
+    #[test]
+    fn dependency_cycle() -> Result<()> {
+        let dir = temp_dir("cycle");
+        let project = |name: &str| {
+            let root = Path::new(&dir).join(name);
+            fs::create_dir_all(root.join("deps")).unwrap();
+            fs::write(
+                root.join("ketchfile"),
+                format!("(name {})\n(version 1)\n", name),
+            )
+            .unwrap();
+            root
+        };
+        let (a, b, c) = (project("a"), project("b"), project("c"));
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&b, a.join("deps/b")).unwrap();
+            std::os::unix::fs::symlink(&a, b.join("deps/a")).unwrap();
+            let e = dependency_build_order(&a, &mut vec![], &mut vec![]).unwrap_err();
+            assert_eq!(
+                e.0,
+                format!("Dependency cycle detected at {}.", canonical(&a).display())
+            );
+            fs::remove_file(b.join("deps/a")).unwrap();
+            std::os::unix::fs::symlink(&b, c.join("deps/b")).unwrap();
+            let mut order = vec![];
+            dependency_build_order(&c, &mut vec![], &mut order)?;
+            assert_eq!(order, vec![canonical(&b)]);
+        }
+        fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn transitive_archives() -> Result<()> {
+        let dir = temp_dir("archives");
+        let root = Path::new(&dir);
+        for (path, name) in [("deps/net", "net"), ("deps/net/deps/buf", "buffer")] {
+            let dep = root.join(path);
+            fs::create_dir_all(&dep).unwrap();
+            fs::write(
+                dep.join("ketchfile"),
+                format!("(name {})\n(version 1)\n", name),
+            )
+            .unwrap();
+            fs::write(dep.join(static_lib_name(name)), "").unwrap();
+        }
+        fs::create_dir_all(root.join("deps/zlib")).unwrap();
+        fs::write(root.join("deps/zlib").join(static_lib_name("zlib")), "").unwrap();
+        let archive = |path: &str, name: &str| {
+            Path::new(".")
+                .join(path)
+                .join(static_lib_name(name))
+                .to_string_lossy()
+                .to_string()
+        };
+        assert_eq!(
+            dependency_archives(root)?,
+            vec![
+                archive("deps/net", "net"),
+                archive("deps/net/deps/buf", "buffer"),
+                archive("deps/zlib", "zlib"),
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn rebased_paths() {
+        let dir = temp_dir("rebase");
+        let root = Path::new(&dir);
+        fs::create_dir_all(root.join("proj/src/net")).unwrap();
+        assert_eq!(
+            rebase("proj/src/net", root, &root.join("proj")),
+            Path::new(".").join("src/net").to_string_lossy()
+        );
+        assert_eq!(
+            rebase("other/x.c", root, &root.join("proj")),
+            root.join("other/x.c").to_string_lossy()
+        );
+        let unchanged = ManifestDir { entered: None };
+        assert_eq!(unchanged.resolve("src/a.c"), "src/a.c");
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -1,17 +1,28 @@
 use crate::{
-    config::{find_val, ConfigValue},
+    config::{get_all, get_bool, get_each, get_list, get_string, resolve_platforms, ConfigValue},
     error,
-    errors::Result,
+    errors::{Error, Result},
+};
+use std::{
+    env,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
 };
-use std::fmt::{self, Display, Formatter};
 
 const DEFAULT_COMPILER: &str = "cc";
+const DEFAULT_CXX_COMPILER: &str = "c++";
+const DEFAULT_CXX_STANDARD: &str = "c++17";
+const DEFAULT_ARCHIVER: &str = "ar";
+const DEFAULT_BUILD_DIR: &str = "./build";
+const DEFAULT_OBJ_EXT: &str = if cfg!(windows) { "obj" } else { "o" };
 const DEFAULT_FLAGS: [&str; 4] = [
     "-Wall",
     "-Wextra",
     "-Wwrite-strings",
     "-Werror=discarded-qualifiers",
 ];
+const DEFAULT_RELEASE_FLAGS: [&str; 2] = ["-O3", "-DNDEBUG"];
+const DEFAULT_DEBUG_FLAGS: [&str; 2] = ["-g", "-O0"];
 const DEFAULT_STANDARD: Standard = Standard {
     std: Std::C99,
     gnu_extensions: false,
@@ -35,181 +46,1023 @@ pub enum Std {
     C17 = 17,
     C23 = 23,
 }
+impl Std {
+    /// The last two digits of the year the standard was published in.
+    pub fn year(&self) -> u8 {
+        *self as u8
+    }
+}
+/// Parses the year part of a standard, e.g. `11` in `gnu11`.
+impl FromStr for Std {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "89" => Ok(Self::C89),
+            "99" => Ok(Self::C99),
+            "11" => Ok(Self::C11),
+            "17" => Ok(Self::C17),
+            "23" | "2x" => Ok(Self::C23),
+            x => error!(
+                "`{}` is not a valid C standard year. Valid years are: 89, 99, 11, 17, 23, 2x.",
+                x
+            ),
+        }
+    }
+}
+#[derive(Copy, Clone)]
 pub struct Standard {
     std: Std,
     gnu_extensions: bool,
 }
+impl Standard {
+    pub fn std(&self) -> Std {
+        self.std
+    }
+    pub fn gnu_extensions(&self) -> bool {
+        self.gnu_extensions
+    }
+}
 impl Display for Standard {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            format!(
-                "{}{}",
-                if self.gnu_extensions { "gnu" } else { "c" },
-                self.std as u8
-            )
-            .replace("23", "2x")
-        )
+        write!(f, "{}", if self.gnu_extensions() { "gnu" } else { "c" })?;
+        match self.std() {
+            /* Compilers released before the standard only know it as `2x`. */
+            Std::C23 => write!(f, "2x"),
+            std => write!(f, "{}", std.year()),
+        }
     }
 }
 pub enum ProjectType {
     Binary,
     Shared,
     Static,
+    /// Both a static and a shared library, built from the same objects.
+    Both,
+}
+impl Display for ProjectType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Binary => "binary",
+                Self::Shared => "shared",
+                Self::Static => "static",
+                Self::Both => "both",
+            }
+        )
+    }
+}
+impl FromStr for ProjectType {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "binary" => Ok(Self::Binary),
+            "shared" => Ok(Self::Shared),
+            "static" => Ok(Self::Static),
+            "both" => Ok(Self::Both),
+            x => error!("`{}` is not a valid project type. Available project types: binary, shared, static, both.", x),
+        }
+    }
+}
+/// A dependency declared with `(dependency SOURCE REPO [TAG])`, installed
+/// under `deps/<name>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dependency {
+    pub source: String,
+    pub repo: String,
+    pub tag: Option<String>,
+}
+impl Dependency {
+    /// The directory the dependency is installed in under `deps/`, the last
+    /// component of its repository.
+    pub fn name(&self) -> &str {
+        self.repo.rsplit('/').next().unwrap_or(&self.repo)
+    }
+    /// Reads the words of a `dependency` entry.
+    pub fn from_words(words: &[String]) -> Result<Self> {
+        match words {
+            [source, repo] => Self::new(source, repo, None),
+            [source, repo, tag] => Self::new(source, repo, Some(tag.clone())),
+            _ => error!(
+                "Key `dependency` must be a source, a repository and optionally a tag, e.g. `(dependency github owner/repo v1.0)`."
+            ),
+        }
+    }
+    fn new(source: &str, repo: &str, tag: Option<String>) -> Result<Self> {
+        if source != "github" {
+            return error!(
+                "`{}` is not a valid dependency source. Available sources: github.",
+                source
+            );
+        }
+        let valid = repo.split_once('/').is_some_and(|(owner, name)| {
+            !owner.is_empty() && !name.is_empty() && !name.contains('/')
+        });
+        if !valid {
+            return error!(
+                "`{}` is not a valid repository. Expected `owner/repo`.",
+                repo
+            );
+        }
+        Ok(Self {
+            source: source.to_string(),
+            repo: repo.to_string(),
+            tag,
+        })
+    }
+    /// The ketchfile entry declaring the dependency.
+    pub fn to_config(&self) -> ConfigValue {
+        let mut words = vec![self.source.clone(), self.repo.clone()];
+        words.extend(self.tag.clone());
+        ConfigValue::Pair(
+            "dependency".to_string(),
+            Box::new(ConfigValue::Array(
+                words.into_iter().map(ConfigValue::Ident).collect(),
+            )),
+        )
+    }
+}
+/// Written as on the command line, `SOURCE:REPO[@TAG]`.
+impl Display for Dependency {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.source, self.repo)?;
+        match &self.tag {
+            Some(tag) => write!(f, "@{}", tag),
+            None => Ok(()),
+        }
+    }
+}
+/// Parses `[SOURCE:]REPO[@TAG]`, the source defaulting to `github`.
+impl FromStr for Dependency {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (source, rest) = s.split_once(':').unwrap_or(("github", s));
+        match rest.split_once('@') {
+            Some((repo, tag)) if !tag.is_empty() => Self::new(source, repo, Some(tag.to_string())),
+            Some(_) => error!("Missing tag after `@` in `{}`.", s),
+            None => Self::new(source, rest, None),
+        }
+    }
 }
 pub struct Project {
     pub name: String,
     pub version: String,
     pub standard: Standard,
     pub compiler: String,
+    /// The compiler of the C++ sources, which also links the project if it
+    /// has any.
+    pub cxx_compiler: String,
+    /// The C++ standard, e.g. `c++17`.
+    pub cxx_standard: String,
     pub flags: Vec<String>,
     pub ptype: ProjectType,
     pub build_script: BuildScript,
+    pub pch: Option<String>,
+    /// Compiles the C sources as one translation unit including them all.
+    pub unity: bool,
+    pub werror: Option<bool>,
+    pub pre_build: Vec<String>,
+    pub post_build: Vec<String>,
+    pub libs: Vec<String>,
+    pub ldflags: Vec<String>,
+    /// The linker passed as `-fuse-ld=<linker>` when linking, e.g. `lld`.
+    pub linker: Option<String>,
+    pub archiver: String,
+    pub ranlib: Option<String>,
+    pub obj_ext: String,
+    /// The flags added by `--release`.
+    pub release_flags: Vec<String>,
+    /// The flags added unless `--release` or `--no-debug` is given.
+    pub debug_flags: Vec<String>,
+    pub description: Option<String>,
+    pub authors: Vec<String>,
+    pub license: Option<String>,
+    pub homepage: Option<String>,
+    pub cc_version_min: Option<Vec<u32>>,
+    pub source_dirs: Vec<String>,
+    pub dependencies: Vec<Dependency>,
+    /// Where objects and generated files go, `./build` unless overridden by
+    /// `--output-dir` or `KETCH_BUILD_DIR`.
+    pub build_dir: String,
 }
+/// Aligned `KEY value` rows, omitting the unset and empty fields.
 impl Display for Project {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        writeln!(f, "CC       {}", self.compiler)?;
-        writeln!(
-            f,
-            "CFLAGS   {}-std={}",
-            self.flags
-                .iter()
-                .fold("".to_string(), |acc, v| format!("{}{} ", acc, v)),
-            self.standard
-        )?;
-        writeln!(
-            f,
-            "TYPE     {}",
-            match self.ptype {
-                ProjectType::Binary => "BIN",
-                ProjectType::Shared => "SHARED",
-                ProjectType::Static => "STATIC",
+        let mut cflags = self.flags.clone();
+        cflags.push(format!("-std={}", self.standard));
+        let mut rows = vec![
+            ("CC", self.compiler.clone()),
+            ("CFLAGS", cflags.join(" ")),
+            (
+                "TYPE",
+                match self.ptype {
+                    ProjectType::Binary => "BIN",
+                    ProjectType::Shared => "SHARED",
+                    ProjectType::Static => "STATIC",
+                    ProjectType::Both => "BOTH",
+                }
+                .to_string(),
+            ),
+            ("NAME", self.name.clone()),
+            ("VERSION", self.version.clone()),
+        ];
+        let optional = vec![
+            ("LDFLAGS", self.ldflags.join(" ")),
+            ("LINKER", self.linker.clone().unwrap_or_default()),
+            (
+                "LIBS",
+                self.libs
+                    .iter()
+                    .map(|lib| format!("-l{}", lib))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            ),
+            ("PCH", self.pch.clone().unwrap_or_default()),
+            ("SRCDIRS", self.source_dirs.join(" ")),
+            (
+                "DEPS",
+                self.dependencies
+                    .iter()
+                    .map(|dep| dep.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            ),
+            ("DESC", self.description.clone().unwrap_or_default()),
+            ("AUTHORS", self.authors.join(", ")),
+            ("LICENSE", self.license.clone().unwrap_or_default()),
+            ("HOMEPAGE", self.homepage.clone().unwrap_or_default()),
+        ];
+        rows.extend(optional.into_iter().filter(|(_, value)| !value.is_empty()));
+        let width = rows.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+        for (i, (key, value)) in rows.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
             }
-        )?;
-        writeln!(f, "NAME     {}", self.name)?;
-        write!(f, "VERSION  {}", self.version)
+            write!(f, "{:<width$} {}", key, value, width = width.max(8))?;
+        }
+        Ok(())
     }
 }
 impl Project {
+    pub fn builder() -> ProjectBuilder {
+        ProjectBuilder::default()
+    }
     pub fn from_config(vals: Vec<ConfigValue>) -> Result<Self> {
-        let name = if let Some(ConfigValue::Array(av)) = find_val(&vals, "name") {
-            get_first(&av, "name")
-        } else {
-            error!("Key `name` must be a single string.")
-        }?;
-        let version = if let Some(ConfigValue::Array(av)) = find_val(&vals, "version") {
-            get_first(&av, "version")
-        } else {
-            error!("Key `version` must be a single string.")
-        }?;
-        let standard = match find_val(&vals, "standard") {
-            None => Ok(DEFAULT_STANDARD),
-            Some(ConfigValue::Array(av)) => {
-                let raw = get_first(&av, "standard")?;
-                if raw.as_str() == "ansi" {
-                    Ok(Standard {
-                        gnu_extensions: false,
-                        std: Std::C89,
-                    })
-                } else {
-                    let prefix = if raw.starts_with("gnu") { "gnu" } else { "c" };
-
-                    let standards = &[Std::C89, Std::C99, Std::C11, Std::C17, Std::C23];
-
-                    Ok(Standard {
-                        gnu_extensions: prefix == "gnu",
-                        std: standards
-                            .iter()
-                            .filter_map(|s| {
-                                if format!("{}{}", prefix, *s as u8) == raw {
-                                    Some(*s)
-                                } else {
-                                    None
-                                }
-                            })
-                            .next()
-                            .map_or(
-                                error!(
-                                    "`{}` is not a valid C standard. Valid standards are: {}",
-                                    raw,
-                                    standards.iter().fold("ansi".to_string(), |acc, v| format!(
-                                        "{}, c{}, gnu{}",
-                                        acc, *v as u8, *v as u8
-                                    ))
-                                ),
-                                Ok,
-                            )?,
-                    })
+        let vals = resolve_platforms(vals, env::consts::OS)?;
+        /* Checked first, since a newer ketchfile may not parse further. */
+        if let Some(raw) = get_string(&vals, "minimum-ketch-version")? {
+            let required = match parse_version(&raw) {
+                Some(required) => required,
+                None => {
+                    return error!(
+                        "`{}` is not a valid version for key `minimum-ketch-version`. Expected e.g. `0.2.0`.",
+                        raw
+                    )
                 }
+            };
+            let have = env!("CARGO_PKG_VERSION");
+            if parse_version(have).is_some_and(|have| is_older(&have, &required)) {
+                return error!("This project requires ketch >= {}, you have {}.", raw, have);
+            }
+        }
+        let mut builder = Project::builder();
+        if let Some(name) = get_string(&vals, "name")? {
+            builder = builder.name(name);
+        }
+        if let Some(version) = get_string(&vals, "version")? {
+            builder = builder.version(version);
+        }
+        if let Some(raw) = get_string(&vals, "standard")? {
+            builder = builder.standard(parse_standard(&raw)?);
+        }
+        if let Some(compiler) = get_string(&vals, "cc")? {
+            builder = builder.compiler(compiler);
+        }
+        if let Some(cxx) = get_string(&vals, "cxx")? {
+            builder = builder.cxx_compiler(cxx);
+        }
+        if let Some(raw) = get_string(&vals, "cxxstd")? {
+            if !is_cxx_standard(&raw) {
+                return error!(
+                    "`{}` is not a valid C++ standard. Expected e.g. `c++17` or `gnu++20`.",
+                    raw
+                );
             }
-            _ => error!("Key `standard` must be a single string."),
-        }?;
-        let compiler = match find_val(&vals, "cc") {
-            None => Ok(DEFAULT_COMPILER.to_string()),
-            Some(ConfigValue::Array(av)) => get_first(&av, "cc"),
-            _ => error!("Key `cc` must be a single string."),
-        }?;
-        let flags = match find_val(&vals, "flags") {
-            None => Ok(DEFAULT_FLAGS.iter().map(|s| s.to_string()).collect()),
-            Some(ConfigValue::Array(av)) => {
-                let mut flags = vec![];
-                for value in av {
-                    if let ConfigValue::Ident(flag) = value {
-                        flags.push(flag);
-                    } else {
-                        return error!("Each flag must be an identifier.");
-                    }
+            builder = builder.cxx_standard(raw);
+        }
+        if let Some(flags) = get_list(&vals, "flags")? {
+            if flags.is_empty() {
+                return error!(
+                    "Key `flags` is empty. Omit it to use the default flags, or build with --no-default-flags."
+                );
+            }
+            builder = builder.flags(flags);
+        }
+        if let Some(extra_flags) = get_list(&vals, "extra-flags")? {
+            builder = builder.extra_flags(extra_flags);
+        }
+        if let Some(ptype) = get_string(&vals, "type")? {
+            builder = builder.ptype(ptype.parse()?);
+        }
+        if let Some(build_script) = get_string(&vals, "build_script")? {
+            builder = builder.build_script(match build_script.as_str() {
+                "none" => BuildScript::None,
+                "only" => BuildScript::Only,
+                "after" => BuildScript::After,
+                "before" => BuildScript::Before,
+                "repeat" => BuildScript::Repeat,
+                x => return error!("`{}` is not a valid build script frequency. Available frequencies: only, after, before, repeat.", x),
+            });
+        }
+        if let Some(pch) = get_string(&vals, "pch")? {
+            builder = builder.pch(pch);
+        }
+        if let Some(unity) = get_bool(&vals, "unity")? {
+            builder = builder.unity(unity);
+        }
+        if let Some(werror) = get_bool(&vals, "werror")? {
+            builder = builder.werror(werror);
+        }
+        if let Some(libs) = get_list(&vals, "libs")? {
+            builder = builder.libs(libs);
+        }
+        if let Some(ldflags) = get_list(&vals, "ldflags")? {
+            builder = builder.ldflags(ldflags);
+        }
+        if let Some(linker) = get_string(&vals, "linker")? {
+            builder = builder.linker(linker);
+        }
+        if let Some(archiver) = get_string(&vals, "ar")? {
+            builder = builder.archiver(archiver);
+        }
+        if let Some(ranlib) = get_string(&vals, "ranlib")? {
+            builder = builder.ranlib(ranlib);
+        }
+        if let Some(obj_ext) = get_string(&vals, "obj-ext")? {
+            builder = builder.obj_ext(obj_ext);
+        }
+        if let Some(release_flags) = get_list(&vals, "release-flags")? {
+            builder = builder.release_flags(release_flags);
+        }
+        if let Some(debug_flags) = get_list(&vals, "debug-flags")? {
+            builder = builder.debug_flags(debug_flags);
+        }
+        if let Some(raw) = get_string(&vals, "cc-version-min")? {
+            match parse_version(&raw) {
+                Some(version) => builder = builder.cc_version_min(version),
+                None => {
+                    return error!(
+                    "`{}` is not a valid version for key `cc-version-min`. Expected e.g. `11.2`.",
+                    raw
+                )
                 }
-                Ok(flags)
             }
-            _ => error!("Key `flags` must be an array."),
-        }?;
-        let ptype = match find_val(&vals, "type") {
-            None => Ok(DEFAULT_PTYPE),
-            Some(ConfigValue::Array(av)) => match get_first(&av, "type")?.as_str() {
-                "binary" => Ok(ProjectType::Binary),
-                "shared" => Ok(ProjectType::Shared),
-                "static" => Ok(ProjectType::Static),
-                x => error!("`{}` is not a valid project type. Available project types: binary, shared, static.", x),
-            },
-            _ => error!("Key `type` must be a single string."),
-        }?;
-        let build_script = match find_val(&vals, "build_script") {
-            None => Ok(BuildScript::None),
-            Some(ConfigValue::Array(av)) => match get_first(&av, "build_script")?.as_str() {
-                "none" => Ok(BuildScript::None),
-                "only" => Ok(BuildScript::Only),
-                "after" => Ok(BuildScript::After),
-                "before" => Ok(BuildScript::Before),
-                "repeat" => Ok(BuildScript::Repeat),
-                x => error!("`{}` is not a valid build script frequency. Available frequencies: only, after, before, repeat.", x),
+        }
+        if let Some(source_dirs) = get_list(&vals, "source-dirs")? {
+            builder = builder.source_dirs(source_dirs);
+        }
+        if let Some(description) = get_string(&vals, "description")? {
+            builder = builder.description(description);
+        }
+        if let Some(authors) = get_list(&vals, "authors")? {
+            builder = builder.authors(authors);
+        }
+        if let Some(license) = get_string(&vals, "license")? {
+            builder = builder.license(license);
+        }
+        if let Some(homepage) = get_string(&vals, "homepage")? {
+            builder = builder.homepage(homepage);
+        }
+        let mut dependencies = vec![];
+        for words in get_each(&vals, "dependency")? {
+            let dependency = Dependency::from_words(&words)?;
+            if dependencies
+                .iter()
+                .any(|dep: &Dependency| dep.name() == dependency.name())
+            {
+                return error!("Dependency `{}` is declared twice.", dependency.name());
             }
-            _ => error!("Key `build_script` must be a single string."),
-        }?;
+            dependencies.push(dependency);
+        }
+        builder
+            .dependencies(dependencies)
+            .pre_build(get_all(&vals, "pre-build")?)
+            .post_build(get_all(&vals, "post-build")?)
+            .build()
+    }
+}
 
-        Ok(Self {
-            name,
-            version,
-            standard,
-            compiler,
-            flags,
-            ptype,
-            build_script,
+/// Builds a `Project` without going through a ketchfile. Unset fields take
+/// the same defaults as missing keys.
+#[derive(Default)]
+pub struct ProjectBuilder {
+    name: Option<String>,
+    version: Option<String>,
+    standard: Option<Standard>,
+    compiler: Option<String>,
+    cxx_compiler: Option<String>,
+    cxx_standard: Option<String>,
+    flags: Option<Vec<String>>,
+    extra_flags: Vec<String>,
+    ptype: Option<ProjectType>,
+    build_script: Option<BuildScript>,
+    pch: Option<String>,
+    unity: bool,
+    werror: Option<bool>,
+    pre_build: Vec<String>,
+    post_build: Vec<String>,
+    libs: Vec<String>,
+    ldflags: Vec<String>,
+    linker: Option<String>,
+    archiver: Option<String>,
+    ranlib: Option<String>,
+    obj_ext: Option<String>,
+    release_flags: Option<Vec<String>>,
+    debug_flags: Option<Vec<String>>,
+    description: Option<String>,
+    authors: Vec<String>,
+    license: Option<String>,
+    homepage: Option<String>,
+    cc_version_min: Option<Vec<u32>>,
+    source_dirs: Vec<String>,
+    dependencies: Vec<Dependency>,
+    build_dir: Option<String>,
+}
+impl ProjectBuilder {
+    pub fn name(mut self, name: impl ToString) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+    pub fn version(mut self, version: impl ToString) -> Self {
+        self.version = Some(version.to_string());
+        self
+    }
+    pub fn standard(mut self, standard: Standard) -> Self {
+        self.standard = Some(standard);
+        self
+    }
+    pub fn compiler(mut self, compiler: impl ToString) -> Self {
+        self.compiler = Some(compiler.to_string());
+        self
+    }
+    pub fn cxx_compiler(mut self, cxx_compiler: impl ToString) -> Self {
+        self.cxx_compiler = Some(cxx_compiler.to_string());
+        self
+    }
+    pub fn cxx_standard(mut self, cxx_standard: impl ToString) -> Self {
+        self.cxx_standard = Some(cxx_standard.to_string());
+        self
+    }
+    pub fn flags(mut self, flags: Vec<String>) -> Self {
+        self.flags = Some(flags);
+        self
+    }
+    /// Flags appended to `flags`, or to the default flags if unset.
+    pub fn extra_flags(mut self, extra_flags: Vec<String>) -> Self {
+        self.extra_flags = extra_flags;
+        self
+    }
+    pub fn ptype(mut self, ptype: ProjectType) -> Self {
+        self.ptype = Some(ptype);
+        self
+    }
+    pub fn build_script(mut self, build_script: BuildScript) -> Self {
+        self.build_script = Some(build_script);
+        self
+    }
+    pub fn pch(mut self, pch: impl ToString) -> Self {
+        self.pch = Some(pch.to_string());
+        self
+    }
+    pub fn unity(mut self, unity: bool) -> Self {
+        self.unity = unity;
+        self
+    }
+    pub fn werror(mut self, werror: bool) -> Self {
+        self.werror = Some(werror);
+        self
+    }
+    pub fn pre_build(mut self, pre_build: Vec<String>) -> Self {
+        self.pre_build = pre_build;
+        self
+    }
+    pub fn post_build(mut self, post_build: Vec<String>) -> Self {
+        self.post_build = post_build;
+        self
+    }
+    pub fn libs(mut self, libs: Vec<String>) -> Self {
+        self.libs = libs;
+        self
+    }
+    pub fn ldflags(mut self, ldflags: Vec<String>) -> Self {
+        self.ldflags = ldflags;
+        self
+    }
+    pub fn linker(mut self, linker: impl ToString) -> Self {
+        self.linker = Some(linker.to_string());
+        self
+    }
+    pub fn archiver(mut self, archiver: impl ToString) -> Self {
+        self.archiver = Some(archiver.to_string());
+        self
+    }
+    pub fn ranlib(mut self, ranlib: impl ToString) -> Self {
+        self.ranlib = Some(ranlib.to_string());
+        self
+    }
+    pub fn obj_ext(mut self, obj_ext: impl ToString) -> Self {
+        self.obj_ext = Some(obj_ext.to_string());
+        self
+    }
+    pub fn release_flags(mut self, release_flags: Vec<String>) -> Self {
+        self.release_flags = Some(release_flags);
+        self
+    }
+    pub fn debug_flags(mut self, debug_flags: Vec<String>) -> Self {
+        self.debug_flags = Some(debug_flags);
+        self
+    }
+    pub fn description(mut self, description: impl ToString) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+    pub fn authors(mut self, authors: Vec<String>) -> Self {
+        self.authors = authors;
+        self
+    }
+    pub fn license(mut self, license: impl ToString) -> Self {
+        self.license = Some(license.to_string());
+        self
+    }
+    pub fn homepage(mut self, homepage: impl ToString) -> Self {
+        self.homepage = Some(homepage.to_string());
+        self
+    }
+    pub fn cc_version_min(mut self, version: Vec<u32>) -> Self {
+        self.cc_version_min = Some(version);
+        self
+    }
+    /// Directories scanned for sources in addition to `./src`.
+    pub fn source_dirs(mut self, source_dirs: Vec<String>) -> Self {
+        self.source_dirs = source_dirs;
+        self
+    }
+    pub fn dependencies(mut self, dependencies: Vec<Dependency>) -> Self {
+        self.dependencies = dependencies;
+        self
+    }
+    pub fn build_dir(mut self, build_dir: impl ToString) -> Self {
+        self.build_dir = Some(build_dir.to_string());
+        self
+    }
+    /// Fills in the defaults, failing if the name or version is missing.
+    pub fn build(self) -> Result<Project> {
+        Ok(Project {
+            name: self
+                .name
+                .map_or(error!("Key `name` must be a single string."), Ok)?,
+            version: self
+                .version
+                .map_or(error!("Key `version` must be a single string."), Ok)?,
+            standard: self.standard.unwrap_or(DEFAULT_STANDARD),
+            compiler: self
+                .compiler
+                .unwrap_or_else(|| DEFAULT_COMPILER.to_string()),
+            cxx_compiler: self
+                .cxx_compiler
+                .unwrap_or_else(|| DEFAULT_CXX_COMPILER.to_string()),
+            cxx_standard: self
+                .cxx_standard
+                .unwrap_or_else(|| DEFAULT_CXX_STANDARD.to_string()),
+            flags: self
+                .flags
+                .unwrap_or_else(|| DEFAULT_FLAGS.iter().map(|s| s.to_string()).collect())
+                .into_iter()
+                .chain(self.extra_flags)
+                .collect(),
+            ptype: self.ptype.unwrap_or(DEFAULT_PTYPE),
+            build_script: self.build_script.unwrap_or(BuildScript::None),
+            pch: self.pch,
+            unity: self.unity,
+            werror: self.werror,
+            pre_build: self.pre_build,
+            post_build: self.post_build,
+            libs: self.libs,
+            ldflags: self.ldflags,
+            linker: self.linker,
+            archiver: self
+                .archiver
+                .unwrap_or_else(|| DEFAULT_ARCHIVER.to_string()),
+            ranlib: self.ranlib,
+            obj_ext: self.obj_ext.unwrap_or_else(|| DEFAULT_OBJ_EXT.to_string()),
+            release_flags: self.release_flags.unwrap_or_else(|| {
+                DEFAULT_RELEASE_FLAGS
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect()
+            }),
+            debug_flags: self
+                .debug_flags
+                .unwrap_or_else(|| DEFAULT_DEBUG_FLAGS.iter().map(|s| s.to_string()).collect()),
+            description: self.description,
+            authors: self.authors,
+            license: self.license,
+            homepage: self.homepage,
+            cc_version_min: self.cc_version_min,
+            source_dirs: self.source_dirs,
+            dependencies: self.dependencies,
+            build_dir: self
+                .build_dir
+                .unwrap_or_else(|| DEFAULT_BUILD_DIR.to_string()),
         })
     }
 }
-fn get_first(av: &[ConfigValue], k: impl ToString) -> Result<String> {
-    let k = k.to_string();
-    if av.len() == 1 {
-        if let ConfigValue::Ident(name) = &av[0] {
-            Ok(name.to_string())
-        } else {
-            error!("Key `{}` must be a single string.", k)
-        }
+
+/// Parses a standard as written in the `standard` key, e.g. `gnu11`.
+pub fn parse_standard(raw: &str) -> Result<Standard> {
+    if raw == "ansi" {
+        return Ok(Standard {
+            gnu_extensions: false,
+            std: Std::C89,
+        });
+    }
+    let parsed = match raw.strip_prefix("gnu") {
+        Some(year) => year.parse().map(|std| (std, true)),
+        None => match raw.strip_prefix('c') {
+            Some(year) => year.parse().map(|std| (std, false)),
+            None => error!("Missing `c` or `gnu` prefix."),
+        },
+    };
+    if let Ok((std, gnu_extensions)) = parsed {
+        return Ok(Standard {
+            std,
+            gnu_extensions,
+        });
+    }
+    let standards = [Std::C89, Std::C99, Std::C11, Std::C17, Std::C23];
+    error!(
+        "`{}` is not a valid C standard. Valid standards are: {}",
+        raw,
+        standards
+            .iter()
+            .fold("ansi".to_string(), |acc, &std| format!(
+                "{}, {}, {}",
+                acc,
+                Standard {
+                    std,
+                    gnu_extensions: false
+                },
+                Standard {
+                    std,
+                    gnu_extensions: true
+                }
+            ))
+    )
+}
+
+/// Whether `raw` is a C++ standard as passed to `-std`, e.g. `c++17` or
+/// `gnu++2b`.
+pub fn is_cxx_standard(raw: &str) -> bool {
+    let year = raw
+        .strip_prefix("c++")
+        .or_else(|| raw.strip_prefix("gnu++"));
+    year.is_some_and(|year| {
+        year.len() == 2
+            && year.starts_with(|c: char| c.is_ascii_digit())
+            && year.ends_with(|c: char| c.is_ascii_alphanumeric())
+    })
+}
+
+/// Parses the dotted version `raw`, e.g. `11.2`. A distribution suffix after
+/// `-`, `+` or `~` is ignored, as in `12.2.0-14`.
+pub fn parse_version(raw: &str) -> Option<Vec<u32>> {
+    let end = raw.find(['-', '+', '~']).unwrap_or(raw.len());
+    raw[..end]
+        .split('.')
+        .map(|n| {
+            if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) {
+                n.parse().ok()
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The kind of compiler, which decides how some flags are spelt.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CompilerFamily {
+    Gcc,
+    Clang,
+    Msvc,
+    Unknown,
+}
+
+/// Tells the compiler family from the output of `cc --version`. clang is
+/// checked first since its output may mention gcc compatibility, and `cl`
+/// prints its banner even though it rejects `--version`.
+pub fn compiler_family(output: &str) -> CompilerFamily {
+    if output.contains("clang") {
+        CompilerFamily::Clang
+    } else if output.contains("Microsoft") {
+        CompilerFamily::Msvc
+    } else if output.contains("gcc") || output.contains("Free Software Foundation") {
+        CompilerFamily::Gcc
     } else {
-        error!("Key `{}` must be a single string.", k)
+        CompilerFamily::Unknown
     }
 }
 
+/// Whether `version` is older than `required`, `12` and `12.0` comparing
+/// equal.
+pub fn is_older(version: &[u32], required: &[u32]) -> bool {
+    let len = version.len().max(required.len());
+    let pad = |version: &[u32]| {
+        let mut version = version.to_vec();
+        version.resize(len, 0);
+        version
+    };
+    pad(version) < pad(required)
+}
+
+/// Finds the version in the first line of `cc --version`: the word after
+/// `version`, as printed by clang (`clang version 15.0.7`), or else the word
+/// after the package version, as printed by gcc (`gcc (Debian 12.2.0-14)
+/// 12.2.0`).
+pub fn compiler_version(output: &str) -> Option<Vec<u32>> {
+    let line = output.lines().next()?;
+    let rest = match line.find(" version ") {
+        Some(i) => &line[i + " version ".len()..],
+        None => &line[line.rfind(") ")? + ") ".len()..],
+    };
+    parse_version(rest.split_whitespace().next()?)
+}
+
 pub mod manager;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn standards() -> Result<()> {
+        let ansi = parse_standard("ansi")?;
+        assert!(matches!(ansi.std(), Std::C89) && !ansi.gnu_extensions());
+        let c99 = parse_standard("c99")?;
+        assert!(matches!(c99.std(), Std::C99) && !c99.gnu_extensions());
+        let gnu11 = parse_standard("gnu11")?;
+        assert!(matches!(gnu11.std(), Std::C11) && gnu11.gnu_extensions());
+        assert_eq!(gnu11.std().year(), 11);
+        let c2x = parse_standard("c2x")?;
+        assert!(matches!(c2x.std(), Std::C23) && !c2x.gnu_extensions());
+        assert_eq!(c2x.to_string(), "c2x");
+        let c23 = parse_standard("c23")?;
+        assert!(matches!(c23.std(), Std::C23) && !c23.gnu_extensions());
+        let gnu23 = parse_standard("gnu23")?;
+        assert!(matches!(gnu23.std(), Std::C23) && gnu23.gnu_extensions());
+        assert!(parse_standard("gnu2x")?.gnu_extensions());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_standard() {
+        let e = parse_standard("c12").err().unwrap();
+        assert!(e
+            .0
+            .ends_with("ansi, c89, gnu89, c99, gnu99, c11, gnu11, c17, gnu17, c2x, gnu2x"));
+        assert!(parse_standard("gnu").is_err());
+    }
+
+    #[test]
+    fn builder() -> Result<()> {
+        let built = Project::builder()
+            .name("x")
+            .version("0.1.0")
+            .ptype(ProjectType::Static)
+            .build()?;
+        let parsed = Project::from_config(crate::config::parse_string(
+            "(name x)\n(version 0.1.0)\n(type static)",
+        )?)?;
+        assert_eq!(built.to_string(), parsed.to_string());
+        assert_eq!(built.compiler, DEFAULT_COMPILER);
+        assert_eq!(built.flags, DEFAULT_FLAGS);
+        assert!(Project::builder().name("x").build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn from_str() {
+        assert!(matches!("binary".parse(), Ok(ProjectType::Binary)));
+        assert!(matches!("both".parse(), Ok(ProjectType::Both)));
+        assert!("Binary".parse::<ProjectType>().is_err());
+        assert!(matches!("99".parse(), Ok(Std::C99)));
+        assert!(matches!("2x".parse(), Ok(Std::C23)));
+        assert!("98".parse::<Std>().is_err());
+        assert!(parse_standard("99").is_err());
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        for std in [Std::C89, Std::C99, Std::C11, Std::C17, Std::C23] {
+            for gnu_extensions in [false, true] {
+                let standard = Standard {
+                    std,
+                    gnu_extensions,
+                };
+                let parsed = parse_standard(&standard.to_string())?;
+                assert_eq!(parsed.std().year(), std.year());
+                assert_eq!(parsed.gnu_extensions(), gnu_extensions);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn metadata() -> Result<()> {
+        let project = Project::from_config(crate::config::parse_string(
+            "(name x)\n(version 1)\n(description \"A test.\")\n(authors a b)\n(license MIT)",
+        )?)?;
+        assert_eq!(project.description.as_deref(), Some("A test."));
+        assert_eq!(project.authors, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(project.license.as_deref(), Some("MIT"));
+        assert_eq!(project.homepage, None);
+        assert!(project
+            .to_string()
+            .ends_with("\nAUTHORS  a, b\nLICENSE  MIT"));
+        let invalid = "(name x)\n(version 1)\n(license MIT Apache-2.0)";
+        assert!(Project::from_config(crate::config::parse_string(invalid)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(parse_version("11.2"), Some(vec![11, 2]));
+        assert_eq!(parse_version("9"), Some(vec![9]));
+        assert_eq!(parse_version("x"), None);
+        assert_eq!(parse_version("11.2abc"), None);
+        assert_eq!(parse_version("1..2"), None);
+        assert_eq!(parse_version("12.2.0-14"), Some(vec![12, 2, 0]));
+        assert_eq!(
+            compiler_version("gcc (Debian 12.2.0-14) 12.2.0\nCopyright"),
+            Some(vec![12, 2, 0])
+        );
+        assert_eq!(
+            compiler_version("Ubuntu clang version 14.0.0-1ubuntu1.1\nTarget: x86_64"),
+            Some(vec![14, 0, 0])
+        );
+        assert_eq!(
+            compiler_version("Apple clang version 15.0.0 (clang-1500.0.40.1)"),
+            Some(vec![15, 0, 0])
+        );
+        assert_eq!(
+            compiler_version("gcc (GCC 2.1) 13.2.1 20230801"),
+            Some(vec![13, 2, 1])
+        );
+        assert_eq!(
+            compiler_version("tcc version 0.9.27 (x86_64 Linux)"),
+            Some(vec![0, 9, 27])
+        );
+        assert_eq!(compiler_version("tcc"), None);
+    }
+
+    #[test]
+    fn extra_flags() -> Result<()> {
+        let flags = |config: &str| -> Result<Vec<String>> {
+            let config = format!("(name x)\n(version 1)\n{}", config);
+            Ok(Project::from_config(crate::config::parse_string(config)?)?.flags)
+        };
+        let mut expected = DEFAULT_FLAGS
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        expected.push("-g".to_string());
+        assert_eq!(flags("(extra-flags -g)")?, expected);
+        assert_eq!(flags("(extra-flags -g)\n(flags -O2)")?, vec!["-O2", "-g"]);
+        assert_eq!(flags("(flags -O2)")?, vec!["-O2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_flags() {
+        let config = crate::config::parse_string("(name x)\n(version 1)\n(flags)").unwrap();
+        let e = Project::from_config(config).err().unwrap();
+        assert_eq!(
+            e.0,
+            "Key `flags` is empty. Omit it to use the default flags, or build with --no-default-flags."
+        );
+    }
+
+    #[test]
+    fn display() -> Result<()> {
+        let project = Project::builder()
+            .name("foo")
+            .version("1.0")
+            .ptype(ProjectType::Shared)
+            .flags(vec!["-Wall".to_string(), "-O2".to_string()])
+            .libs(vec!["m".to_string(), "pthread".to_string()])
+            .homepage("https://example.org")
+            .build()?;
+        assert_eq!(
+            project.to_string(),
+            "CC       cc
+CFLAGS   -Wall -O2 -std=c99
+TYPE     SHARED
+NAME     foo
+VERSION  1.0
+LIBS     -lm -lpthread
+HOMEPAGE https://example.org"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dependencies() -> Result<()> {
+        let dep: Dependency = "github:owner/repo@v1.0".parse()?;
+        assert_eq!(dep.name(), "repo");
+        assert_eq!(dep.tag.as_deref(), Some("v1.0"));
+        assert_eq!(
+            dep.to_config().to_string(),
+            "(dependency github owner/repo v1.0)"
+        );
+        assert_eq!(
+            "owner/repo".parse::<Dependency>()?.to_string(),
+            "github:owner/repo"
+        );
+        for invalid in ["gitlab:owner/repo", "repo", "owner/repo@", "a/b/c"] {
+            assert!(invalid.parse::<Dependency>().is_err(), "{}", invalid);
+        }
+        let project = Project::from_config(crate::config::parse_string(
+            "(name x)\n(version 1)\n(dependency github a/b)\n(dependency github c/d v2)",
+        )?)?;
+        assert_eq!(project.dependencies.len(), 2);
+        assert_eq!(project.dependencies[1], "c/d@v2".parse()?);
+        assert!(Project::from_config(crate::config::parse_string(
+            "(name x)\n(version 1)\n(dependency github a/b)\n(dependency github c/b)",
+        )?)
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cxx_standards() {
+        for raw in ["c++98", "c++17", "gnu++20", "c++2b"] {
+            assert!(is_cxx_standard(raw), "{}", raw);
+        }
+        for raw in ["c17", "c++", "c++2017", "gnu17"] {
+            assert!(!is_cxx_standard(raw), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn compiler_families() {
+        assert_eq!(
+            compiler_family(
+                "gcc (Debian 12.2.0-14) 12.2.0\nCopyright (C) 2022 Free Software Foundation, Inc."
+            ),
+            CompilerFamily::Gcc
+        );
+        assert_eq!(
+            compiler_family(
+                "cc (GCC) 13.2.1 20230801\nCopyright (C) 2023 Free Software Foundation, Inc."
+            ),
+            CompilerFamily::Gcc
+        );
+        assert_eq!(
+            compiler_family("Apple clang version 15.0.0 (clang-1500.0.40.1)"),
+            CompilerFamily::Clang
+        );
+        assert_eq!(
+            compiler_family("Microsoft (R) C/C++ Optimizing Compiler Version 19.38.33130 for x64"),
+            CompilerFamily::Msvc
+        );
+        assert_eq!(
+            compiler_family("tcc version 0.9.27"),
+            CompilerFamily::Unknown
+        );
+    }
+
+    #[test]
+    fn minimum_ketch_version() -> Result<()> {
+        let project = |required: &str| {
+            Project::from_config(crate::config::parse_string(format!(
+                "(name x)\n(version 1)\n(minimum-ketch-version {})",
+                required
+            ))?)
+        };
+        let e = project("999.0").err().unwrap();
+        assert_eq!(
+            e.0,
+            format!(
+                "This project requires ketch >= 999.0, you have {}.",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        project(env!("CARGO_PKG_VERSION"))?;
+        project("0.0.1")?;
+        assert!(project("latest").is_err());
+        assert!(is_older(&[0, 1], &[0, 1, 1]));
+        assert!(!is_older(&[12], &[12, 0]));
+        Ok(())
+    }
+}